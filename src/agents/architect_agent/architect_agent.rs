@@ -1,40 +1,69 @@
 use async_trait::async_trait;
 use crossterm::style::Color;
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 
-use crate::{agents::base::{agent_attributes::AgentAttributes, agent_traits::{AgentState, AgentTraits, AsyncExecuteFunctions}}, ai_functions::ai_functions::{print_project_scope, print_site_urls}, function_string, models::general::{llm::LLMModel, project::{ProjectScope, ProjectSpec, UserInputs}}, utils::{command_line::LogMessage, helper::check_url_status_code, llm_requests::make_llm_request_decoded}};
+use crate::{agents::base::{agent_attributes::AgentAttributes, agent_traits::{AgentState, AgentTraits, AsyncExecuteFunctions}}, ai_functions::ai_functions::{print_project_scope, print_site_urls}, function_string, models::general::{config::AcadiaConfig, llm::ImageAttachment, llm_error::LlmError, project::{ProjectScope, ProjectSpec, UserInputs}}, providers::{model_router::route_provider, provider_registry::{ProviderRegistry, GPT4O}, provider_traits::ProviderHandle}, utils::{candidate_selection::MajorityVote, cancellation::check_cancelled, command_line::LogMessage, files_io::{read_image_attachments, GENERATED_CODE_DIR}, helper::{check_url_status_code, EXTERNAL_URL_TIMEOUT}, http_client::shared_client, json_repair::decode_llm_json, llm_requests::{api_instruction_wrapper, attach_images, make_llm_request_best_of, make_llm_request_decoded}, tool_calling::{builtin_tools, make_llm_request_with_tools, CHECK_URL}}};
 
 #[derive(Debug, Clone)]
 pub struct ArchitectAgent {
-    attributes: AgentAttributes
+    attributes: AgentAttributes,
+    llm_provider: ProviderHandle
 }
 
 impl ArchitectAgent {
 
-    pub fn new() -> Self {
+    /// Agent answering with the LLM the user picked for the run, GPT-4o when nothing was picked
+    pub fn with_llm_choice(llm_choice: Option<&str>) -> Self {
+        Self::with_provider(ProviderRegistry::global().run_provider(llm_choice, GPT4O))
+    }
+
+    /// Agent that sends its requests to `llm_provider` instead of the default provider
+    pub fn with_provider(llm_provider: ProviderHandle) -> Self {
         let attributes = AgentAttributes::new(
             "Gathers information and design solutions for website development".to_string(),
             "Solutions Architect".to_string()
         );
         ArchitectAgent {
-            attributes
+            attributes,
+            llm_provider
         }
     }
 
-    async fn get_project_scope(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>) {
+    async fn get_project_scope(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>, images: &[ImageAttachment], cancel: &CancellationToken) -> Result<(), LlmError> {
         
         let project_description = format!("{:?}", project_spec.read().await.project_description);
 
-        let project_scope: ProjectScope = make_llm_request_decoded::<ProjectScope>(
-            LLMModel::GPT4o("GPT4o".to_owned()), 
-            print_project_scope, 
-            project_description, 
-            &self.attributes.position, 
-            &self.attributes.state, 
-            function_string!(print_project_scope)
-        ).await;
+        let operation = function_string!(print_project_scope);
+        let candidates = AcadiaConfig::global().best_of.candidates_for(operation);
+        let project_scope: ProjectScope = if candidates > 1 {
+            let answer = make_llm_request_best_of(
+                self.llm_provider.clone(),
+                print_project_scope,
+                project_description,
+                images,
+                &self.attributes.position,
+                &self.attributes.state,
+                operation,
+                candidates,
+                &MajorityVote::<ProjectScope>::new(),
+                cancel
+            ).await?;
+            decode_llm_json(&answer)?
+        } else {
+            make_llm_request_decoded::<ProjectScope>(
+                self.llm_provider.clone(),
+                print_project_scope, 
+                project_description, 
+                images,
+                &self.attributes.position, 
+                &self.attributes.state, 
+                operation,
+                cancel
+            ).await?
+        };
 
         if let Ok(mut proj_spec) = project_spec.try_write() {
             proj_spec.project_scope = Some(project_scope);
@@ -43,65 +72,91 @@ impl ArchitectAgent {
             panic!("Exiting Architect agent");
         }
         // project_spec.write().await.project_scope = Some(project_scope);
+        Ok(())
     }
 
-    async fn get_external_urls(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>) -> Vec<String> {
+    /// Models that can call tools check the urls they pick with `check_url` and swap out the ones that do not answer
+    async fn get_external_urls(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>, images: &[ImageAttachment], cancel: &CancellationToken) -> Result<Vec<String>, LlmError> {
         
         let project_description = project_spec.read().await.project_description.clone().expect("Project description not found");
 
-        let external_urls: Vec<String> = make_llm_request_decoded(
-            LLMModel::GPT4o("GPT4o".to_owned()), 
+        let operation = function_string!(print_site_urls);
+        if route_provider(self.llm_provider.clone(), &self.attributes.position, operation)?.supports_tools() {
+            let mut messages = attach_images(api_instruction_wrapper(print_site_urls, &project_description), images);
+            if let Some(function_input) = messages.last_mut() {
+                function_input.content.push_str("Check every url with the check_url tool first and only print urls that answer with status 200.");
+            }
+            let answer = make_llm_request_with_tools(
+                self.llm_provider.clone(),
+                &builtin_tools(GENERATED_CODE_DIR).select(&[CHECK_URL]),
+                messages,
+                &self.attributes.position,
+                &self.attributes.state,
+                operation,
+                cancel
+            ).await?;
+            return decode_llm_json(&answer);
+        }
+
+        make_llm_request_decoded(
+            self.llm_provider.clone(),
             print_site_urls, 
             project_description, 
+            images,
             &self.attributes.position, 
             &self.attributes.state, 
-            function_string!(print_site_urls)
-        ).await;
-
-        external_urls
+            operation,
+            cancel
+        ).await
 
     }
 
-    async fn test_external_urls(&mut self, external_urls: &Vec<String>) -> Vec<String> {
+    /// Urls that answer with status 200, unreachable urls are left out
+    async fn test_external_urls(&mut self, external_urls: &Vec<String>) -> Result<Vec<String>, LlmError> {
         let mut valid_external_urls = Vec::<String>::new();
 
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(5))
-            .build()
-            .expect("Could not create reqwest Client");
+        let client = shared_client()?;
 
         for url in external_urls.iter() {
-            if check_url_status_code(&client, url).await.unwrap() == 200 {
-                valid_external_urls.push(url.to_owned());
-            } else {
-                LogMessage::Error.print_message(format!("External URL error: {}", url).as_str(), Color::Red);
+            match check_url_status_code(&client, url, EXTERNAL_URL_TIMEOUT).await {
+                Ok(200) => valid_external_urls.push(url.to_owned()),
+                Ok(status) => LogMessage::Error.print_message(format!("External URL error: {} answered with status {}", url, status).as_str(), Color::Red),
+                Err(e) => LogMessage::Error.print_message(format!("External URL error: {}: {}", url, e).as_str(), Color::Red)
             }
         }
 
-        valid_external_urls
+        Ok(valid_external_urls)
     }
 }
 
 #[async_trait]
 impl AsyncExecuteFunctions for ArchitectAgent {
 
+    fn agent_provider(&self) -> (&str, ProviderHandle) {
+        (&self.attributes.position, self.llm_provider.clone())
+    }
+
     async fn execute_workflow(
         &mut self,
         project_spec: &mut Arc<RwLock<ProjectSpec>>,
-        user_input: Box<Arc<UserInputs>>
+        user_input: Box<Arc<UserInputs>>,
+        cancel: &CancellationToken
     ) -> Result<(), Box<dyn std::error::Error>> {
         LogMessage::Info.print_message(
             "Solutions Architect beginning workflow...", 
             Color::Rgb { r: 19, g: 214, b: 185 }
         );
         let user_input = *user_input;
+        // Diagrams the user attached help scope the project and spot the external APIs it needs
+        let images = read_image_attachments(&user_input.image_paths)?;
         let mut external_urls: Vec<String> = vec![];
         while self.attributes.state != AgentState::Completed {
+            check_cancelled(cancel)?;
 
             match self.attributes.state {
                 AgentState::Discovery => {
                     
-                    self.get_project_scope(project_spec).await;
+                    self.get_project_scope(project_spec, &images, cancel).await?;
 
                     // Check if external urls are required
                     if project_spec.read().await.project_scope.unwrap().is_external_urls_required {
@@ -112,7 +167,7 @@ impl AsyncExecuteFunctions for ArchitectAgent {
                                 
                 },
                 AgentState::Working => {
-                    external_urls = self.get_external_urls(project_spec).await;
+                    external_urls = self.get_external_urls(project_spec, &images, cancel).await?;
 
                     self.attributes.update_agent_state(AgentState::UnitTesting);
                 },
@@ -124,7 +179,7 @@ impl AsyncExecuteFunctions for ArchitectAgent {
                         continue;
                     }
 
-                    let validated_external_urls = self.test_external_urls(&external_urls).await;
+                    let validated_external_urls = self.test_external_urls(&external_urls).await?;
                     println!("These are the validated external URLs: {:?}", validated_external_urls);
 
                     if let Ok(ref mut proj_spec) = project_spec.try_write() {
@@ -149,11 +204,142 @@ impl AsyncExecuteFunctions for ArchitectAgent {
 #[cfg(test)]
 mod tests {
 
+    use std::sync::Mutex;
+
+    use crate::{models::general::{generation::GenerationParams, llm::{LLMCompletion, Message}, tools::{ToolCall, ToolCompletion, ToolDefinition, ToolTurn}}, providers::{mock_provider::MockProvider, provider_traits::LlmProvider}};
+
     use super::*;
 
+    /// Scopes every project as needing external urls and checks a url with `check_url` before listing it
+    #[derive(Debug, Default)]
+    struct UrlCheckingProvider {
+        offered_tools: Mutex<Vec<String>>,
+        tool_results: Mutex<Vec<String>>
+    }
+
+    #[async_trait]
+    impl LlmProvider for UrlCheckingProvider {
+        fn name(&self) -> &str {
+            "UrlChecking"
+        }
+
+        fn model(&self) -> String {
+            "url-checking".to_string()
+        }
+
+        fn supports_tools(&self) -> bool {
+            true
+        }
+
+        async fn send_messages(&self, _messages: Vec<Message>, _params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+            let content = r#"{"is_crud_required": false, "is_user_login": false, "is_external_urls_required": true}"#.to_string();
+            Ok(LLMCompletion { content, provider: self.name().to_string(), model: self.model(), usage: None })
+        }
+
+        async fn send_with_tools(&self, conversation: Vec<ToolTurn>, tools: &[ToolDefinition], _params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+            *self.offered_tools.lock().unwrap() = tools.iter().map(|tool| tool.name.clone()).collect();
+            let result = conversation.iter().find_map(|turn| match turn {
+                ToolTurn::ToolResult { content, .. } => Some(content.clone()),
+                _ => None
+            });
+
+            let tool_calls = match result {
+                Some(result) => {
+                    self.tool_results.lock().unwrap().push(result);
+                    Vec::new()
+                },
+                // Rejected by the http client before anything is sent
+                None => vec![ToolCall { id: "call_0".to_string(), name: CHECK_URL.to_string(), arguments: serde_json::json!({ "url": "not a url" }) }]
+            };
+            Ok(ToolCompletion {
+                completion: LLMCompletion { content: "[]".to_string(), provider: self.name().to_string(), model: self.model(), usage: None },
+                tool_calls
+            })
+        }
+    }
+
     #[test]
     fn test_create_architect_agent() {
-        let architect = ArchitectAgent::new();
+        let architect = ArchitectAgent::with_llm_choice(None);
         dbg!(architect);
     }
-}
\ No newline at end of file
+
+    async fn run_architect(mock: Arc<MockProvider>) -> Arc<RwLock<ProjectSpec>> {
+        let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
+        project_spec.write().await.project_description = Some("build a website that tracks todo items".to_string());
+
+        let mut architect = ArchitectAgent::with_provider(mock);
+        architect.execute_workflow(&mut project_spec, Box::new(Arc::new(UserInputs::new())), &CancellationToken::new()).await.unwrap();
+        assert_eq!(architect.attributes.state, AgentState::Completed);
+        project_spec
+    }
+
+    #[tokio::test]
+    async fn test_architect_without_external_urls() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("print_project_scope", r#"{"is_crud_required": true, "is_user_login": false, "is_external_urls_required": false}"#);
+
+        let project_spec = run_architect(mock.clone()).await;
+
+        assert!(!project_spec.read().await.project_scope.unwrap().is_external_urls_required);
+        assert!(project_spec.read().await.external_urls.is_none());
+        assert!(mock.requests_for("print_site_urls").is_empty());
+        mock.assert_prompt_contains("print_project_scope", "tracks todo items");
+    }
+
+    #[tokio::test]
+    async fn test_architect_with_external_urls() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("print_project_scope", r#"{"is_crud_required": false, "is_user_login": false, "is_external_urls_required": true}"#)
+            .respond("print_site_urls", "[]");
+
+        let project_spec = run_architect(mock.clone()).await;
+
+        assert!(project_spec.read().await.project_scope.unwrap().is_external_urls_required);
+        assert_eq!(mock.requests_for("print_site_urls").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_architect_drops_unreachable_urls() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("print_project_scope", r#"{"is_crud_required": false, "is_user_login": false, "is_external_urls_required": true}"#)
+            .respond("print_site_urls", r#"["not a url"]"#);
+
+        let project_spec = run_architect(mock.clone()).await;
+
+        assert_eq!(project_spec.read().await.external_urls, Some(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn test_architect_sees_attached_diagram() {
+        let diagram = std::env::temp_dir().join("acadia_test_architecture.png");
+        std::fs::write(&diagram, [0x89, b'P', b'N', b'G']).unwrap();
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("print_project_scope", r#"{"is_crud_required": true, "is_user_login": false, "is_external_urls_required": false}"#);
+        let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
+        project_spec.write().await.project_description = Some("build a website that tracks todo items".to_string());
+        let mut user_input = UserInputs::new();
+        user_input.image_paths = vec![diagram.to_str().unwrap().to_string()];
+
+        let mut architect = ArchitectAgent::with_provider(mock.clone());
+        architect.execute_workflow(&mut project_spec, Box::new(Arc::new(user_input)), &CancellationToken::new()).await.unwrap();
+
+        let images = &mock.requests_for("print_project_scope")[0].messages[1].images;
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].media_type, "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_architect_checks_urls_with_tools() {
+        let provider = Arc::new(UrlCheckingProvider::default());
+        let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
+        project_spec.write().await.project_description = Some("build a website that shows crypto prices".to_string());
+
+        let mut architect = ArchitectAgent::with_provider(provider.clone());
+        architect.execute_workflow(&mut project_spec, Box::new(Arc::new(UserInputs::new())), &CancellationToken::new()).await.unwrap();
+
+        assert_eq!(architect.attributes.state, AgentState::Completed);
+        assert_eq!(*provider.offered_tools.lock().unwrap(), vec![CHECK_URL.to_string()]);
+        assert!(provider.tool_results.lock().unwrap()[0].starts_with("Error:"));
+    }
+}
@@ -3,30 +3,53 @@ use std::{process::{Command, Stdio}, sync::Arc};
 use async_trait::async_trait;
 use crossterm::style::Color;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 
-use crate::{agents::base::{agent_attributes::AgentAttributes, agent_traits::{AgentState, AgentTraits, AsyncExecuteFunctions}}, ai_functions::ai_functions::print_backend_webserver_code, function_string, models::general::{llm::LLMModel, project::{ProjectSpec, UserInputs}}, utils::{command_line::LogMessage, files_io::{read_code_template_contents, write_code_template_contents}, llm_requests::make_llm_request}};
+use crate::{agents::base::{agent_attributes::AgentAttributes, agent_traits::{AgentState, AgentTraits, AsyncExecuteFunctions}}, ai_functions::ai_functions::print_backend_webserver_code, function_string, models::general::{config::AcadiaConfig, llm_error::LlmError, project::{ProjectSpec, UserInputs}}, providers::{model_router::route_provider, provider_registry::{ProviderRegistry, GPT4O}, provider_traits::ProviderHandle}, utils::{candidate_selection::{FirstPassing, LlmJudge, OrElse}, cancellation::check_cancelled, command_line::LogMessage, files_io::{read_code_template_contents, write_code_template_contents, GENERATED_CODE_DIR, GENERATED_CODE_FILE}, llm_requests::{api_instruction_wrapper, make_llm_request_best_of, make_llm_request_streamed}, token_counting::{estimate_message_tokens, prompt_budget, truncate_to_tokens}}};
+
+/// Room kept for the note appended to a truncated code template
+const TRUNCATION_MARKER_TOKENS: u64 = 32;
 
 
 #[derive(Debug, Clone)]
 pub struct BackendAgent {
     attributes: AgentAttributes,
-    bug_counts: u8
+    bug_counts: u8,
+    llm_provider: ProviderHandle,
+    /// Directory of the generated project the code is written to
+    code_dir: String
 }
 
 impl BackendAgent {
 
-    pub fn new() -> Self {
+    /// Agent answering with the LLM the user picked for the run, GPT-4o when nothing was picked
+    pub fn with_llm_choice(llm_choice: Option<&str>) -> Self {
+        Self::with_provider(ProviderRegistry::global().run_provider(llm_choice, GPT4O))
+    }
+
+    /// Agent that sends its requests to `llm_provider` instead of the default provider
+    pub fn with_provider(llm_provider: ProviderHandle) -> Self {
         let attributes = AgentAttributes::new(
             "Develops the backend code for webserver and json database".to_owned(),
             "Backend Developer".to_owned()
         );
         BackendAgent {
             attributes,
-            bug_counts: 0
+            bug_counts: 0,
+            llm_provider,
+            code_dir: GENERATED_CODE_DIR.to_string()
         }
     }
 
-    async fn generate_backend_code(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>, preferred_language: Option<String>) {
+    /// Writes the generated code to `code_dir` instead of the generated project, keeps test runs out of the repo
+    #[cfg(test)]
+    pub fn with_code_dir(mut self, code_dir: &str) -> Self {
+        self.code_dir = code_dir.to_string();
+        self
+    }
+
+    /// The generated code is only written once the whole answer arrived, a cancelled run leaves `main.rs` untouched
+    async fn generate_backend_code(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>, preferred_language: Option<String>, cancel: &CancellationToken) -> Result<(), LlmError> {
         if preferred_language == None {
             LogMessage::Error.print_message("No language was selected. Exiting program", Color::Red);
             panic!();
@@ -34,6 +57,12 @@ impl BackendAgent {
         let code_template = read_code_template_contents(preferred_language.clone().unwrap());
 
         let project_description = project_spec.as_ref().read().await.project_description.clone().unwrap();
+        let description_without_template = format!(
+            "CODE TEMPLATE:  \n PROJECT DESCRIPTION: {} \n built using {}",
+            project_description, 
+            preferred_language.clone().unwrap()
+        );
+        let code_template = self.fit_code_template(code_template, &description_without_template)?;
         let extended_description = format!(
             "CODE TEMPLATE: {} \n PROJECT DESCRIPTION: {} \n built using {}",
             code_template, 
@@ -42,23 +71,69 @@ impl BackendAgent {
         );
 
         if let Ok(ref mut proj_spec) = project_spec.try_write() {
-            let backend_code = make_llm_request(
-                LLMModel::GPT4o("GPT4o".to_owned()), 
-                print_backend_webserver_code, 
-                extended_description, 
-                &self.attributes.position, 
-                &self.attributes.state, 
-                function_string!(print_backend_webserver_code)
-            ).await;
-            // proj_spec.backend_code = Some(backend_code);
-            println!("{}", backend_code);
-
-            write_code_template_contents(&backend_code, preferred_language.clone().unwrap())
+            let operation = function_string!(print_backend_webserver_code);
+            let candidates = AcadiaConfig::global().best_of.candidates_for(operation);
+            // Candidates are built in a scratch copy of the generated project, the one that compiles is kept.
+            // When none compiles a judge picks the one closest to working.
+            let backend_code = if candidates > 1 {
+                make_llm_request_best_of(
+                    self.llm_provider.clone(),
+                    print_backend_webserver_code,
+                    extended_description,
+                    &[],
+                    &self.attributes.position,
+                    &self.attributes.state,
+                    operation,
+                    candidates,
+                    &OrElse::new(
+                        FirstPassing::compiles(&self.code_dir, GENERATED_CODE_FILE)?,
+                        LlmJudge::new(self.llm_provider.clone(), "the most complete Rust webserver, closest to compiling", cancel.clone())
+                    ),
+                    cancel
+                ).await?
+            } else {
+                make_llm_request_streamed(
+                    self.llm_provider.clone(),
+                    print_backend_webserver_code, 
+                    extended_description, 
+                    &self.attributes.position, 
+                    &self.attributes.state, 
+                    operation,
+                    cancel
+                ).await?
+            };
+            proj_spec.backend_code = Some(backend_code.clone());
+
+            write_code_template_contents(&backend_code, &self.code_dir);
+            Ok(())
         } else {
             panic!("Failed to generate backend code");
         }
     }
 
+    /// Cuts the code template down to what fits the context window of the model the request is routed to,
+    /// the project description and instructions are always sent whole
+    fn fit_code_template(&self, code_template: String, rest_of_prompt: &str) -> Result<String, LlmError> {
+        let operation = function_string!(print_backend_webserver_code);
+        let model = route_provider(self.llm_provider.clone(), &self.attributes.position, operation)?.model();
+        let params = AcadiaConfig::global().generation.params_for(operation);
+        let Some(budget) = prompt_budget(&model, &params) else {
+            return Ok(code_template);
+        };
+
+        let rest_tokens = estimate_message_tokens(&model, &api_instruction_wrapper(print_backend_webserver_code, rest_of_prompt));
+        let (kept, truncated) = truncate_to_tokens(&model, &code_template, budget.saturating_sub(rest_tokens + TRUNCATION_MARKER_TOKENS));
+        if !truncated {
+            return Ok(kept);
+        }
+
+        LogMessage::Error.print_message(
+            &format!("Code template is too large for the context window of {}, sending the first {} characters", model, kept.len()),
+            Color::Yellow
+        );
+        Ok(format!("{}\n// ... rest of the code template truncated to fit the context window", kept))
+    }
+
     fn build_backend_code(&self, code_path: &str, preferred_language: String) {
         LogMessage::Info.print_message("Building backend code to ensure no errors", Color::Green);
 
@@ -77,23 +152,30 @@ impl BackendAgent {
 #[async_trait]
 impl AsyncExecuteFunctions for BackendAgent {
 
+    fn agent_provider(&self) -> (&str, ProviderHandle) {
+        (&self.attributes.position, self.llm_provider.clone())
+    }
+
     // Apply selected language to the object of the backend agent
     async fn execute_workflow(
         &mut self, 
         project_spec: &mut Arc<RwLock<ProjectSpec>>, 
-        user_input: Box<Arc<UserInputs>>
+        user_input: Box<Arc<UserInputs>>,
+        cancel: &CancellationToken
     ) -> Result<(), Box<dyn std::error::Error>> {
 
         while self.attributes.state != AgentState::Completed {
+            check_cancelled(cancel)?;
             match self.attributes.state {
                 AgentState::Discovery => {
                     let preferred_language = user_input.backend_language.clone();
-                    self.generate_backend_code(project_spec, preferred_language).await;
+                    self.generate_backend_code(project_spec, preferred_language, cancel).await?;
 
                     self.attributes.update_agent_state(AgentState::Working);
                 },
+                // Building and testing the generated code is not automated yet, the agent is done once the code is written
                 AgentState::Working => {
-                    
+                    self.attributes.update_agent_state(AgentState::Completed);
                 },
                 AgentState::UnitTesting => {
 
@@ -112,6 +194,8 @@ impl AsyncExecuteFunctions for BackendAgent {
 mod tests {
 
 
+    use crate::providers::llama_provider::LlamaProvider;
+
     use super::*;
 
     #[tokio::test]
@@ -119,13 +203,33 @@ mod tests {
         let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
         project_spec.write().await.project_description = Some("Build a very simple todo app with just a get and post route".to_string());
 
-        let mut backend_agent = BackendAgent::new();
-        backend_agent.generate_backend_code(&mut project_spec, Some("Rust".to_string())).await;
+        let code_dir = std::env::temp_dir().join("acadia_test_generate_backend_code");
+        std::fs::create_dir_all(&code_dir).unwrap();
+
+        let mut backend_agent = BackendAgent::with_provider(ProviderRegistry::with_cassettes().get(GPT4O).unwrap()).with_code_dir(code_dir.to_str().unwrap());
+        backend_agent.generate_backend_code(&mut project_spec, Some("Rust".to_string()), &CancellationToken::new()).await.unwrap();
+
+        let backend_code = project_spec.read().await.backend_code.clone().unwrap();
+        assert_eq!(std::fs::read_to_string(code_dir.join(GENERATED_CODE_FILE)).unwrap(), backend_code);
+    }
+
+    #[test]
+    fn test_fit_code_template_to_context_window() {
+        let backend_agent = BackendAgent::with_provider(Arc::new(LlamaProvider::new()));
+        let small_template = "fn main() {}".to_string();
+        let large_template = "// route handler\n".repeat(4_000);
+
+        let small = backend_agent.fit_code_template(small_template.clone(), "Build a todo app").unwrap();
+        let large = backend_agent.fit_code_template(large_template.clone(), "Build a todo app").unwrap();
+
+        assert_eq!(small, small_template);
+        assert!(large.len() < large_template.len());
+        assert!(large.ends_with("truncated to fit the context window"));
     }
 
     #[test]
     fn test_build_backend_code() {
-        let backend_agent = BackendAgent::new();
+        let backend_agent = BackendAgent::with_llm_choice(None);
         backend_agent.build_backend_code("/Users/allenli/Projects/full_applications/Acadia/acadia/generated_code", "".to_string());
     }
 }
\ No newline at end of file
@@ -1,8 +1,9 @@
 use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 
-use crate::models::general::project::{ProjectSpec, UserInputs};
+use crate::{models::general::project::{ProjectSpec, UserInputs}, providers::provider_traits::ProviderHandle};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum AgentState {
@@ -19,9 +20,15 @@ pub trait AgentTraits {
 #[async_trait]
 pub trait AsyncExecuteFunctions {
 
+    /// Position of the agent and the provider it sends requests to unless routing says otherwise
+    fn agent_provider(&self) -> (&str, ProviderHandle);
+
+    /// Runs the agent until it completes. Once `cancel` fires the request in flight is aborted and the
+    /// agent stops before its next step with `LlmError::Cancelled`.
     async fn execute_workflow(
         &mut self, 
         project_spec: &mut Arc<RwLock<ProjectSpec>>,
-        user_input: Box<Arc<UserInputs>>
+        user_input: Box<Arc<UserInputs>>,
+        cancel: &CancellationToken
     ) -> Result<(), Box<dyn std::error::Error>>; 
 }
\ No newline at end of file
@@ -1,9 +1,10 @@
 use std::{sync::Arc, thread};
 use tokio::{sync::RwLock, task, try_join};
+use tokio_util::sync::CancellationToken;
 
 use crossterm::style::Color;
 
-use crate::{models::general::project::{ProjectSpec, UserInputs}, utils::command_line::LogMessage};
+use crate::{models::general::{config::AcadiaConfig, llm_error::LlmError, project::{ProjectSpec, UserInputs}}, providers::{provider_registry::ProviderRegistry, provider_traits::ProviderHandle}, utils::{audit_log::audit_log_path, command_line::LogMessage, preflight::run_preflight, files_io::{write_project_spec, GENERATED_CODE_DIR}, usage_tracking::usage_ledger}};
 
 use super::agent_traits::AsyncExecuteFunctions;
 
@@ -12,7 +13,8 @@ use super::agent_traits::AsyncExecuteFunctions;
 pub struct ProjectWorkflow {
     pub agents: Vec<Box<dyn AsyncExecuteFunctions>>,
     pub project_spec: Arc<RwLock<ProjectSpec>>,
-    pub user_input: Box<Arc<UserInputs>>
+    pub user_input: Box<Arc<UserInputs>>,
+    cancel: CancellationToken
 }
 
 impl ProjectWorkflow {
@@ -20,50 +22,140 @@ impl ProjectWorkflow {
         ProjectWorkflow {
             agents: Vec::new(),
             project_spec: Arc::new(RwLock::new(ProjectSpec::new())),
-            user_input
+            user_input,
+            cancel: CancellationToken::new()
         }
     }
 
+    /// Cancelling this token stops the run, the agent at work is interrupted at its next safe point
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
     pub fn add_agent(&mut self, agent: Box<dyn AsyncExecuteFunctions>) {
         self.agents.push(agent);
     }
 
-    pub async fn initiate_workflow(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>) {
+    /// Checks every provider the agents and the routing table will use before any request is sent
+    pub async fn preflight(&self, ping: bool) -> Result<(), LlmError> {
+        let agent_providers: Vec<(String, ProviderHandle)> = self.agents
+            .iter()
+            .map(|agent| {
+                let (position, provider) = agent.agent_provider();
+                (position.to_string(), provider)
+            })
+            .collect();
+
+        run_preflight(&agent_providers, &AcadiaConfig::global().routing, ProviderRegistry::global(), ping).await
+    }
+
+    /// Runs every agent in order, stopping at the first agent that fails.
+    /// The token and cost summary of the run is printed either way. When the run stops early
+    /// the partial project spec is saved beside the generated code. Running out of budget or
+    /// cancelling the run is a clean stop, any other failure is returned.
+    pub async fn initiate_workflow(&mut self, project_spec: &mut Arc<RwLock<ProjectSpec>>) -> Result<(), Box<dyn std::error::Error>> {
         LogMessage::Info.print_message(
             "Initiating project workflow", 
             Color::Rgb { r: 19, g: 214, b: 185}
         );
-        for agent in &mut self.agents {
-            let _ = agent.execute_workflow(
+        let mut workflow_res = Ok(());
+        let mut stopped_at = self.agents.len();
+        for (index, agent) in self.agents.iter_mut().enumerate() {
+            if let Err(e) = agent.execute_workflow(
                 &mut self.project_spec, 
-                self.user_input.clone()
-            ).await;
+                self.user_input.clone(),
+                &self.cancel
+            ).await {
+                workflow_res = Err(e);
+                stopped_at = index;
+                break;
+            }
+        }
+
+        usage_ledger().print_summary();
+        if let Some(path) = audit_log_path() {
+            LogMessage::Info.print_message(&format!("LLM exchanges logged to {}", path.display()), Color::Rgb { r: 19, g: 214, b: 185 });
+        }
+
+        if let Err(e) = workflow_res {
+            LogMessage::Error.print_message(&format!("Project workflow stopped: {}", e), Color::Red);
+            match write_project_spec(&*self.project_spec.read().await, GENERATED_CODE_DIR) {
+                Ok(path) => LogMessage::Info.print_message(&format!("Partial project spec saved to {}", path), Color::Yellow),
+                Err(save_err) => LogMessage::Error.print_message(&format!("Could not save partial project spec: {}", save_err), Color::Red)
+            }
+
+            match e.downcast_ref::<LlmError>() {
+                Some(LlmError::BudgetExceeded(_)) => return Ok(()),
+                Some(LlmError::Cancelled) => {
+                    let positions: Vec<&str> = self.agents.iter().map(|agent| agent.agent_provider().0).collect();
+                    LogMessage::Error.print_message(&interrupted_agents_report(&positions, stopped_at), Color::Yellow);
+                    return Ok(());
+                },
+                _ => return Err(e)
+            }
         }
+        Ok(())
     }
 }
 
+/// Which agent a cancelled run interrupted, the agents before it completed and the ones after it never started
+fn interrupted_agents_report(positions: &[&str], stopped_at: usize) -> String {
+    let list = |positions: &[&str]| if positions.is_empty() { "none".to_string() } else { positions.join(", ") };
+    let stopped_at = stopped_at.min(positions.len());
+
+    format!(
+        "Run cancelled | Completed: {} | Interrupted: {} | Not started: {}",
+        list(&positions[..stopped_at]),
+        positions.get(stopped_at).copied().unwrap_or("none"),
+        list(positions.get(stopped_at + 1..).unwrap_or_default())
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{sync::Arc, time::Duration};
-    use tokio::{sync::RwLock, time::sleep};
-    use crate::{agents::{architect_agent::architect_agent::ArchitectAgent, backend_agent::{self, backend_agent::BackendAgent}, base::{agent_attributes::AgentAttributes, agent_traits::AgentState}, manager_agent::manager_agent::ManagerAgent}, models::general::project::ProjectSpec, utils::command_line::project_details};
+    use crate::{agents::{architect_agent::architect_agent::ArchitectAgent, backend_agent::backend_agent::BackendAgent, manager_agent::manager_agent::ManagerAgent}, providers::provider_registry::{GPT4O, LLAMA3}};
 
     use super::*;
 
     #[tokio::test]
     async fn test_initiate_workflow() {
-        // Note: Start local-llm to reduce cost of making paid LLM requests
-        let user_input: UserInputs = project_details();
+        // Replays the cassettes under cassettes/, run with ACADIA_CASSETTE=record to answer from the real providers again.
+        // Any OpenAI compatible server (Ollama, vLLM, llama.cpp, LM Studio) works by setting LLM_BASE_URL
+        let mut user_input = UserInputs::new();
+        user_input.project_to_build = "Build a very simple todo app with just a get and post route".to_string();
+        user_input.project_focus = Some("Backend".to_string());
+        user_input.backend_language = Some("Rust".to_string());
         let input_ptr: Box<Arc<UserInputs>> = Box::new(Arc::new(user_input));
         let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
 
-        let manager_agent = ManagerAgent::new();
-        let architect_agent = ArchitectAgent::new();
-        let backend_agent = BackendAgent::new();
+        let registry = ProviderRegistry::with_cassettes();
+        let manager_agent = ManagerAgent::with_provider(registry.get(LLAMA3).unwrap());
+        let architect_agent = ArchitectAgent::with_provider(registry.get(GPT4O).unwrap());
+        let code_dir = std::env::temp_dir().join("acadia_test_initiate_workflow");
+        std::fs::create_dir_all(&code_dir).unwrap();
+        let backend_agent = BackendAgent::with_provider(registry.get(GPT4O).unwrap()).with_code_dir(code_dir.to_str().unwrap());
         let mut project_workflow = ProjectWorkflow::new(input_ptr.clone());
         project_workflow.add_agent(Box::new(manager_agent));
         project_workflow.add_agent(Box::new(architect_agent));
         project_workflow.add_agent(Box::new(backend_agent));
-        project_workflow.initiate_workflow(&mut project_spec).await;
+        project_workflow.initiate_workflow(&mut project_spec).await.unwrap();
+
+        let project_spec = project_workflow.project_spec.read().await;
+        assert!(project_spec.project_description.is_some());
+        assert!(project_spec.backend_code.is_some());
+    }
+
+    #[test]
+    fn test_interrupted_agents_report() {
+        let positions = ["Project Manager", "Solutions Architect", "Backend Developer"];
+
+        assert_eq!(
+            interrupted_agents_report(&positions, 1),
+            "Run cancelled | Completed: Project Manager | Interrupted: Solutions Architect | Not started: Backend Developer"
+        );
+        assert_eq!(
+            interrupted_agents_report(&positions, 0),
+            "Run cancelled | Completed: none | Interrupted: Project Manager | Not started: Solutions Architect, Backend Developer"
+        );
     }
 }
\ No newline at end of file
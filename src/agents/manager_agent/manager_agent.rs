@@ -1,37 +1,48 @@
-use std::{borrow::BorrowMut, sync::Arc, time::Duration};
+use std::sync::Arc;
 
 use async_trait::async_trait;
-use crossterm::style::{Attribute, Color};
-use tokio::{sync::RwLock, time};
-use crate::{agents::base::{agent_attributes::AgentAttributes, agent_traits::{AgentState, AgentTraits, AsyncExecuteFunctions}}, ai_functions::ai_functions::convert_user_input_to_goal, function_string, models::general::{llm::LLMModel, project::{ProjectSpec, UserInputs}}, utils::{command_line::LogMessage, llm_requests::make_llm_request}};
+use crossterm::style::Color;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use crate::{agents::base::{agent_attributes::AgentAttributes, agent_traits::{AgentState, AgentTraits, AsyncExecuteFunctions}}, ai_functions::ai_functions::convert_user_input_to_goal, function_string, models::general::{llm::ImageAttachment, llm_error::LlmError, project::{ProjectSpec, UserInputs}}, providers::{provider_registry::{ProviderRegistry, LLAMA3}, provider_traits::ProviderHandle}, utils::{cancellation::check_cancelled, command_line::LogMessage, files_io::read_image_attachments, llm_requests::make_llm_request}};
 
 #[derive(Debug, Clone)]
 pub struct ManagerAgent {
-    pub attributes: AgentAttributes
+    pub attributes: AgentAttributes,
+    llm_provider: ProviderHandle
 }
 
 impl ManagerAgent {
-    pub fn new() -> Self {
+    /// Agent answering with the LLM the user picked for the run, Llama3 when nothing was picked
+    pub fn with_llm_choice(llm_choice: Option<&str>) -> Self {
+        Self::with_provider(ProviderRegistry::global().run_provider(llm_choice, LLAMA3))
+    }
+
+    /// Agent that sends its requests to `llm_provider` instead of the default provider
+    pub fn with_provider(llm_provider: ProviderHandle) -> Self {
         let attributes = AgentAttributes::new(
             "manage agents that are bulding the application for the end user".to_string(),
             "Project Manager".to_string()
         );
         ManagerAgent {
             attributes,
+            llm_provider
         }
     }
 
-    /// Step 1. Generate a project description for Solutions Architect agent to interpret
-    async fn articulate_project_description(&mut self, user_req: String, llm_model: LLMModel) -> String {
-        let res = make_llm_request(
-            llm_model,
+    /// Step 1. Generate a project description for Solutions Architect agent to interpret,
+    /// mockups the user attached are shown to the model along with the request
+    async fn articulate_project_description(&mut self, user_req: String, images: &[ImageAttachment], cancel: &CancellationToken) -> Result<String, LlmError> {
+        make_llm_request(
+            self.llm_provider.clone(),
             convert_user_input_to_goal, 
             user_req, 
+            images,
             &self.attributes.position, 
             &self.attributes.state,
-            function_string!(convert_user_input_to_goal)
-        ).await;
-        res
+            function_string!(convert_user_input_to_goal),
+            cancel
+        ).await
     }
 }
 
@@ -40,10 +51,15 @@ impl ManagerAgent {
 #[async_trait]
 impl AsyncExecuteFunctions for ManagerAgent {
 
+    fn agent_provider(&self) -> (&str, ProviderHandle) {
+        (&self.attributes.position, self.llm_provider.clone())
+    }
+
     async fn execute_workflow(
         &mut self,
         project_spec: &mut Arc<RwLock<ProjectSpec>>,
-        user_input: Box<Arc<UserInputs>>
+        user_input: Box<Arc<UserInputs>>,
+        cancel: &CancellationToken
     ) -> Result<(), Box<dyn std::error::Error>>{
         LogMessage::Info.print_message(
             "Manager agent beginning workflow...", 
@@ -53,12 +69,14 @@ impl AsyncExecuteFunctions for ManagerAgent {
         // println!("{:?}", self);
         // println!("{:p}", &*user_input);
         let user_input = *user_input;
+        let images = read_image_attachments(&user_input.image_paths)?;
         while self.attributes.state != AgentState::Completed {
+            check_cancelled(cancel)?;
 
             match self.attributes.state {
                 AgentState::Discovery => {
                     // Make request to LLM and articulate project description
-                    let project_description = self.articulate_project_description(user_input.project_to_build.clone(), LLMModel::Llama3("Llama3".to_string())).await;
+                    let project_description = self.articulate_project_description(user_input.project_to_build.clone(), &images, cancel).await?;
                     if let Ok(mut spec) = project_spec.try_write() {
                         spec.project_description = Some(project_description.clone());
                     } else {
@@ -78,13 +96,40 @@ impl AsyncExecuteFunctions for ManagerAgent {
 #[cfg(test)]
 mod tests {
 
-    use crate::ai_functions::ai_functions::convert_user_input_to_goal;
+    use crate::{ai_functions::ai_functions::convert_user_input_to_goal, providers::mock_provider::MockProvider};
 
     use super::*;
 
-    #[test]
-    fn create_manager_agent() {
-        todo!()
+    #[tokio::test]
+    async fn create_manager_agent() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("convert_user_input_to_goal", "build a website that tracks todo items");
+
+        let mut manager = ManagerAgent::with_provider(mock.clone());
+        let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
+        let mut user_input = UserInputs::new();
+        user_input.project_to_build = "I need a simple TODO app".to_string();
+
+        manager.execute_workflow(&mut project_spec, Box::new(Arc::new(user_input)), &CancellationToken::new()).await.unwrap();
+
+        assert_eq!(manager.attributes.state, AgentState::Completed);
+        assert_eq!(project_spec.read().await.project_description.as_deref(), Some("build a website that tracks todo items"));
+        mock.assert_prompt_contains("convert_user_input_to_goal", "I need a simple TODO app");
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_manager_stops_before_request() {
+        let mock = Arc::new(MockProvider::new());
+        let mut manager = ManagerAgent::with_provider(mock.clone());
+        let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let err = manager.execute_workflow(&mut project_spec, Box::new(Arc::new(UserInputs::new())), &cancel).await.unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<LlmError>(), Some(LlmError::Cancelled)));
+        assert_eq!(manager.attributes.state, AgentState::Discovery);
+        assert!(mock.requests().is_empty());
     }
 
     #[test]
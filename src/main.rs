@@ -1,10 +1,9 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use agents::{architect_agent::architect_agent::ArchitectAgent, backend_agent::backend_agent::BackendAgent, base::development_workflow::ProjectWorkflow, manager_agent::manager_agent::ManagerAgent};
-use dialoguer::Select;
-use models::general::project::{ProjectSpec, UserInputs};
+use models::general::{config::AcadiaConfig, project::{ProjectSpec, UserInputs}};
 use tokio::sync::RwLock;
-use utils::command_line::project_details;
+use utils::{cancellation::cancel_on_ctrl_c, command_line::{project_details, CliArgs}, response_cache::ResponseCache};
 
 
 mod ai_functions;
@@ -12,22 +11,38 @@ mod agents;
 mod macros;
 #[macro_use]
 mod models;
+mod providers;
 mod utils;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send>>{
+async fn main() -> Result<(), Box<dyn std::error::Error>>{
     println!("Welcome to Acadia - an AI engineering tool!");
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut config = AcadiaConfig::load()?;
+    let cli_args = CliArgs::parse(&args)?;
+
+    if cli_args.clear_cache {
+        let cache = ResponseCache::new(&config.cache.dir, Duration::from_secs(config.cache.ttl_secs));
+        println!("Removed {} cached LLM response(s) from {}", cache.clear()?, config.cache.dir);
+        return Ok(());
+    }
+    cli_args.apply(&mut config);
+    AcadiaConfig::set_global(config);
     
     let user_input: UserInputs = project_details();
+    let llm_choice: Option<String> = user_input.llm_model.clone();
     let input_ptr: Box<Arc<UserInputs>> = Box::new(Arc::new(user_input));
 
     let mut project_workflow = ProjectWorkflow::new(input_ptr);
     let mut project_spec = Arc::new(RwLock::new(ProjectSpec::new()));
-    project_workflow.add_agent(Box::new(ManagerAgent::new()));
-    project_workflow.add_agent(Box::new(ArchitectAgent::new()));
-    project_workflow.add_agent(Box::new(BackendAgent::new()));
+    project_workflow.add_agent(Box::new(ManagerAgent::with_llm_choice(llm_choice.as_deref())));
+    project_workflow.add_agent(Box::new(ArchitectAgent::with_llm_choice(llm_choice.as_deref())));
+    project_workflow.add_agent(Box::new(BackendAgent::with_llm_choice(llm_choice.as_deref())));
 
-    project_workflow.initiate_workflow(&mut project_spec).await;
+    project_workflow.preflight(cli_args.ping).await?;
+    cancel_on_ctrl_c(project_workflow.cancellation_token());
+    project_workflow.initiate_workflow(&mut project_spec).await?;
 
     // let project_spec = tokio::sync::RwLock::with_max_readers(Box::new(ProjectSpec {
     //     project_description: Some("A simple restaurant application".to_string()),
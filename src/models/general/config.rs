@@ -0,0 +1,75 @@
+use dotenv::dotenv;
+use serde::Deserialize;
+use std::{env, fs, path::Path, sync::OnceLock};
+
+use crate::{models::general::{generation::GenerationConfig, model_capabilities::CapabilityTable, pricing::PriceTable}, providers::model_router::RoutingConfig, utils::{audit_log::AuditConfig, candidate_selection::BestOfConfig, http_client::HttpConfig, json_repair::DecodePolicy, llm_retry::RetryPolicy, rate_limiter::RateLimitConfig, response_cache::CacheConfig, usage_tracking::RunBudget}};
+
+/// Default location of the run configuration, override with the `ACADIA_CONFIG` env variable
+const CONFIG_PATH: &str = "acadia.json";
+
+static CONFIG: OnceLock<AcadiaConfig> = OnceLock::new();
+
+/// Run wide settings read from `acadia.json`. Every section is optional and falls back to its defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AcadiaConfig {
+    pub retry: RetryPolicy,
+    /// Per model prices used for the cost report, keyed by model name
+    pub pricing: PriceTable,
+    pub budget: RunBudget,
+    pub cache: CacheConfig,
+    pub decode: DecodePolicy,
+    /// Generation parameters keyed by AI function name
+    pub generation: GenerationConfig,
+    /// Candidates sampled per AI function, the agent's selector keeps one
+    pub best_of: BestOfConfig,
+    pub routing: RoutingConfig,
+    /// Requests, tokens per minute and in-flight requests keyed by provider name
+    pub rate_limits: RateLimitConfig,
+    /// Context window and output limits keyed by model name
+    pub models: CapabilityTable,
+    pub audit: AuditConfig,
+    pub http: HttpConfig
+}
+
+impl AcadiaConfig {
+
+    /// Reads the config file, a missing file means every default applies
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        dotenv().ok();
+        let path = env::var("ACADIA_CONFIG").unwrap_or_else(|_| CONFIG_PATH.to_string());
+
+        if !Path::new(&path).exists() {
+            return Ok(AcadiaConfig::default());
+        }
+        let contents = fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Makes `config` the one returned by `global`, for settings adjusted after loading (e.g. CLI flags).
+    /// Has no effect once the config has been used.
+    pub fn set_global(config: AcadiaConfig) {
+        let _ = CONFIG.set(config);
+    }
+
+    /// Config shared by every agent, loaded once on first use
+    pub fn global() -> &'static AcadiaConfig {
+        CONFIG.get_or_init(|| AcadiaConfig::load().unwrap_or_else(|e| {
+            panic!("Could not read Acadia config file: {}", e);
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config: AcadiaConfig = serde_json::from_str(r#"{ "retry": { "max_attempts": 5 } }"#).unwrap();
+
+        assert_eq!(config.retry.max_attempts, 5);
+        assert_eq!(config.retry.initial_backoff_ms, RetryPolicy::default().initial_backoff_ms);
+    }
+}
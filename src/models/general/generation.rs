@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Sampling settings sent along with a request, unset fields use the provider's defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationParams {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub seed: Option<u64>,
+    pub stop: Option<Vec<String>>,
+    /// Ask the provider to only answer with a JSON object
+    pub json_mode: Option<bool>
+}
+
+impl GenerationParams {
+
+    /// Fields set in `overrides` replace the ones in `self`
+    pub fn merge(self, overrides: &GenerationParams) -> Self {
+        GenerationParams {
+            temperature: overrides.temperature.or(self.temperature),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            seed: overrides.seed.or(self.seed),
+            stop: overrides.stop.clone().or(self.stop),
+            json_mode: overrides.json_mode.or(self.json_mode)
+        }
+    }
+
+    pub fn is_json_mode(&self) -> bool {
+        self.json_mode.unwrap_or(false)
+    }
+
+    /// Names of the parameters that are set, used to warn about the ones a provider ignores
+    pub fn set_params(&self) -> Vec<&'static str> {
+        [
+            ("temperature", self.temperature.is_some()),
+            ("max_tokens", self.max_tokens.is_some()),
+            ("seed", self.seed.is_some()),
+            ("stop", self.stop.is_some()),
+            ("json_mode", self.is_json_mode())
+        ]
+        .into_iter()
+        .filter(|(_, is_set)| *is_set)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// Generation parameters keyed by AI function name. Entries from the config file are merged over the built in ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct GenerationConfig {
+    overrides: HashMap<String, GenerationParams>
+}
+
+/// Built in parameters, code generation gets room to write whole files and classification is kept deterministic
+fn default_params(ai_function: &str) -> GenerationParams {
+    match ai_function {
+        "print_backend_webserver_code" => GenerationParams {
+            temperature: Some(0.2),
+            max_tokens: Some(8192),
+            ..GenerationParams::default()
+        },
+        "print_project_scope" => GenerationParams {
+            temperature: Some(0.0),
+            json_mode: Some(true),
+            ..GenerationParams::default()
+        },
+        // JSON mode only allows objects, the url list is an array
+        "print_site_urls" => GenerationParams {
+            temperature: Some(0.0),
+            ..GenerationParams::default()
+        },
+        _ => GenerationParams::default()
+    }
+}
+
+impl GenerationConfig {
+    pub fn params_for(&self, ai_function: &str) -> GenerationParams {
+        match self.overrides.get(ai_function) {
+            Some(overrides) => default_params(ai_function).merge(overrides),
+            None => default_params(ai_function)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_config_overrides_merge_over_defaults() {
+        let config: GenerationConfig = serde_json::from_str(
+            r#"{ "print_backend_webserver_code": { "max_tokens": 16000 }, "convert_user_input_to_goal": { "seed": 7 } }"#
+        ).unwrap();
+
+        let backend = config.params_for("print_backend_webserver_code");
+        assert_eq!(backend.max_tokens, Some(16000));
+        assert_eq!(backend.temperature, Some(0.2));
+
+        assert_eq!(config.params_for("convert_user_input_to_goal").set_params(), vec!["seed"]);
+        assert!(config.params_for("print_project_scope").is_json_mode());
+        assert!(config.params_for("unknown_function").set_params().is_empty());
+    }
+}
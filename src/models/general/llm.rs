@@ -1,17 +1,15 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Value};
 
+use crate::providers::provider_registry::{CLAUDE, GPT4O, LLAMA3};
 
-
-#[derive(Debug, Serialize, Deserialize)]
-pub enum LLMModel {
-    Llama3(String),
-    GPT4o(String)
-}
+use super::generation::GenerationParams;
 
 pub fn llm_choices() -> Vec<String> {
     vec![
-        "GPT-4o".to_string(),
-        "Llama3".to_string()
+        GPT4O.to_string(),
+        CLAUDE.to_string(),
+        LLAMA3.to_string()
     ]
 }
 
@@ -38,27 +36,125 @@ pub struct LLMResponse {
     pub generated_text: String
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Message {
     pub role: String,
-    pub content: String
+    pub content: String,
+    /// Sent along with the text, only providers with vision support accept them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageAttachment>
+}
+
+/// Image file encoded for a request, such as a UI mockup or an architecture diagram
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageAttachment {
+    /// MIME type, `image/png` for example
+    pub media_type: String,
+    /// Base64 of the file contents
+    pub data: String
+}
+
+impl ImageAttachment {
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.data)
+    }
+}
+
+/// Chat completions message, one with images has a list of `text` and `image_url` parts as its content
+pub fn openai_message_json(message: &Message) -> Value {
+    if message.images.is_empty() {
+        return json!({ "role": message.role, "content": message.content });
+    }
+
+    let mut parts = vec![json!({ "type": "text", "text": message.content })];
+    parts.extend(message.images.iter().map(|image| json!({ "type": "image_url", "image_url": { "url": image.data_url() } })));
+    json!({ "role": message.role, "content": parts })
+}
+
+/// Messages API message, images go first as base64 `image` blocks followed by the text
+pub fn anthropic_message_json(message: &Message) -> Value {
+    if message.images.is_empty() {
+        return json!({ "role": message.role, "content": message.content });
+    }
+
+    let mut blocks: Vec<Value> = message.images
+        .iter()
+        .map(|image| json!({ "type": "image", "source": { "type": "base64", "media_type": image.media_type, "data": image.data } }))
+        .collect();
+    blocks.push(json!({ "type": "text", "text": message.content }));
+    json!({ "role": message.role, "content": blocks })
+}
+
+fn serialize_openai_messages<S: Serializer>(messages: &[Message], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(messages.iter().map(openai_message_json))
+}
+
+fn serialize_anthropic_messages<S: Serializer>(messages: &[Message], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(messages.iter().map(anthropic_message_json))
 }
 
 #[derive(Serialize, Debug)]
 pub struct LLMRequestBody {
     pub model: String,
-    pub messages: Vec<Message>
+    #[serde(serialize_with = "serialize_openai_messages")]
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>
 }
 
 impl LLMRequestBody {
     pub fn new(messages: Vec<Message>, model: String) -> Self {
         LLMRequestBody {
             model,
-            messages
+            messages,
+            stream: false,
+            stream_options: None,
+            temperature: None,
+            max_tokens: None,
+            seed: None,
+            stop: None,
+            response_format: None
+        }
+    }
+
+    /// Copies every generation parameter, all of them exist in the OpenAI chat completions API
+    pub fn with_params(mut self, params: &GenerationParams) -> Self {
+        self.temperature = params.temperature;
+        self.max_tokens = params.max_tokens;
+        self.seed = params.seed;
+        self.stop = params.stop.clone();
+        if params.is_json_mode() {
+            self.response_format = Some(ResponseFormat { format_type: "json_object".to_string() });
         }
+        self
     }
 }
 
+/// OpenAI `response_format`, `json_object` restricts the answer to a JSON object
+#[derive(Serialize, Debug)]
+pub struct ResponseFormat {
+    #[serde(rename = "type")]
+    pub format_type: String
+}
+
+/// Asks OpenAI to send token usage as the last chunk of a stream
+#[derive(Serialize, Debug)]
+pub struct StreamOptions {
+    pub include_usage: bool
+}
+
 #[derive(Debug, Deserialize)]
 pub struct OpenAIAPIMessage {
     pub content: String
@@ -70,7 +166,249 @@ pub struct OpenAIAPIChoice {
 }
 
 
+#[derive(Debug, Deserialize)]
+pub struct OpenAIUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64
+}
+
 #[derive(Debug, Deserialize)]
 pub struct OpenAIResponse {
-    pub choices: Vec<OpenAIAPIChoice>
+    pub choices: Vec<OpenAIAPIChoice>,
+    #[serde(default)]
+    pub usage: Option<OpenAIUsage>
+}
+
+/// Assistant message of an answer to a request with tools, `content` is null when the model only calls tools
+#[derive(Debug, Deserialize)]
+pub struct OpenAIToolMessage {
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<OpenAIToolCall>
+}
+
+/// `arguments` is a JSON object serialized as a string
+#[derive(Debug, Deserialize)]
+pub struct OpenAIFunctionCall {
+    pub name: String,
+    pub arguments: String
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIToolCall {
+    pub id: String,
+    pub function: OpenAIFunctionCall
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIToolChoice {
+    pub message: OpenAIToolMessage
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIToolResponse {
+    pub choices: Vec<OpenAIToolChoice>,
+    #[serde(default)]
+    pub usage: Option<OpenAIUsage>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIStreamDelta {
+    #[serde(default)]
+    pub content: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIStreamChoice {
+    pub delta: OpenAIStreamDelta
+}
+
+/// One `data:` event of a streamed chat completion, the last one only carries usage
+#[derive(Debug, Deserialize)]
+pub struct OpenAIStreamChunk {
+    pub choices: Vec<OpenAIStreamChoice>,
+    #[serde(default)]
+    pub usage: Option<OpenAIUsage>
+}
+
+/// Anthropic Messages API request, the system prompt lives outside of the messages list
+#[derive(Serialize, Debug)]
+pub struct AnthropicRequestBody {
+    pub model: String,
+    pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(serialize_with = "serialize_anthropic_messages")]
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    pub block_type: String,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// `id`, `name` and `input` are only set on `tool_use` blocks
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub input: Option<serde_json::Value>
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AnthropicUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicResponse {
+    pub content: Vec<AnthropicContentBlock>,
+    pub model: String,
+    #[serde(default)]
+    pub usage: Option<AnthropicUsage>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicStreamDelta {
+    #[serde(default)]
+    pub text: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicStreamMessage {
+    #[serde(default)]
+    pub usage: Option<AnthropicUsage>
+}
+
+/// One `data:` event of a streamed Messages API response. `content_block_delta` carries text,
+/// `message_start` the prompt usage and `message_delta` the completion usage.
+#[derive(Debug, Deserialize)]
+pub struct AnthropicStreamEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub delta: Option<AnthropicStreamDelta>,
+    #[serde(default)]
+    pub message: Option<AnthropicStreamMessage>,
+    #[serde(default)]
+    pub usage: Option<AnthropicUsage>
+}
+
+/// Tokens billed for one request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    /// Takes the counts of a later usage report of the same stream, reports carry running totals
+    /// so they replace the counts seen so far. Counts missing from the report (zero) are kept.
+    pub fn update(&mut self, latest: TokenUsage) {
+        if latest.prompt_tokens > 0 {
+            self.prompt_tokens = latest.prompt_tokens;
+        }
+        if latest.completion_tokens > 0 {
+            self.completion_tokens = latest.completion_tokens;
+        }
+    }
+}
+
+impl From<OpenAIUsage> for TokenUsage {
+    fn from(usage: OpenAIUsage) -> Self {
+        TokenUsage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens
+        }
+    }
+}
+
+impl From<AnthropicUsage> for TokenUsage {
+    fn from(usage: AnthropicUsage) -> Self {
+        TokenUsage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens
+        }
+    }
+}
+
+/// Provider agnostic answer to a chat request, `usage` is None when the provider does not report it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LLMCompletion {
+    pub content: String,
+    pub provider: String,
+    pub model: String,
+    pub usage: Option<TokenUsage>
+}
+
+/// Item of a streamed answer. Usage may arrive in several reports, each one holds the totals so far.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LLMStreamEvent {
+    Delta(String),
+    Usage(TokenUsage)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn message_with_mockup() -> Message {
+        Message {
+            role: "user".to_string(),
+            content: "Build this todo app".to_string(),
+            images: vec![ImageAttachment { media_type: "image/png".to_string(), data: "iVBORw0KGgo=".to_string() }]
+        }
+    }
+
+    #[test]
+    fn test_openai_image_parts() {
+        let body = serde_json::to_value(LLMRequestBody::new(vec![message_with_mockup()], "gpt-4o".to_string())).unwrap();
+
+        assert_eq!(body["messages"][0]["content"][0], json!({ "type": "text", "text": "Build this todo app" }));
+        assert_eq!(body["messages"][0]["content"][1]["image_url"]["url"], "data:image/png;base64,iVBORw0KGgo=");
+        assert!(body["messages"][0].get("images").is_none());
+    }
+
+    #[test]
+    fn test_anthropic_image_blocks() {
+        let text_only = Message { role: "user".to_string(), content: "Build a todo app".to_string(), images: Vec::new() };
+
+        let with_image = anthropic_message_json(&message_with_mockup());
+
+        assert_eq!(with_image["content"][0]["source"], json!({ "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo=" }));
+        assert_eq!(with_image["content"][1]["text"], "Build this todo app");
+        assert_eq!(anthropic_message_json(&text_only), json!({ "role": "user", "content": "Build a todo app" }));
+    }
+
+    #[test]
+    fn test_usage_update_replaces_running_totals() {
+        // Anthropic: message_start reports 1 output token, message_delta the cumulative count
+        let mut usage = TokenUsage { prompt_tokens: 25, completion_tokens: 1 };
+
+        usage.update(TokenUsage { prompt_tokens: 0, completion_tokens: 15 });
+
+        assert_eq!(usage, TokenUsage { prompt_tokens: 25, completion_tokens: 15 });
+    }
 }
@@ -0,0 +1,113 @@
+use std::{fmt, time::Duration};
+
+/// Everything that can go wrong between building an LLM request and decoding its answer
+#[derive(Debug)]
+pub enum LlmError {
+    /// A credential, url or model name is missing or unusable
+    MissingConfig(String),
+    /// The request never got an HTTP response (DNS, connection, timeout, ...)
+    Transport(String),
+    /// The provider answered with a non success status code
+    HttpStatus { status: u16, body: String },
+    /// The provider answered with 429, `retry_after` is taken from the `Retry-After` header when present
+    RateLimited { retry_after: Option<Duration>, body: String },
+    /// The provider answered but not in the shape we expected
+    MalformedResponse(String),
+    /// The answer text could not be deserialized into the requested type
+    Decode { error: String, raw: String },
+    /// The run already spent its token or USD budget, no further requests are sent
+    BudgetExceeded(String),
+    /// Cassette replay is on and no recorded interaction matches the request
+    UnmatchedCassette(String),
+    /// The prompt leaves no room for an answer in the model's context window
+    ContextLengthExceeded { model: String, prompt_tokens: u64, context_tokens: u64 },
+    /// Tool calling is unsupported by the provider or the model kept calling tools without answering
+    ToolCalling(String),
+    /// The run was cancelled while the request was waiting or in flight, the request was aborted
+    Cancelled,
+    /// The request carries images and the provider's model can not read them
+    VisionUnsupported { provider: String, model: String },
+    /// Reading or writing a local file the request depends on failed
+    Io(String)
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::MissingConfig(msg) => write!(f, "LLM configuration error: {}", msg),
+            LlmError::Transport(msg) => write!(f, "Could not reach LLM service: {}", msg),
+            LlmError::HttpStatus { status, body } => write!(f, "LLM service responded with status {}: {}", status, body),
+            LlmError::RateLimited { retry_after: Some(wait), body } => write!(f, "LLM service rate limited the request, retry after {}s: {}", wait.as_secs(), body),
+            LlmError::RateLimited { retry_after: None, body } => write!(f, "LLM service rate limited the request: {}", body),
+            LlmError::MalformedResponse(msg) => write!(f, "Malformed LLM response: {}", msg),
+            LlmError::Decode { error, raw } => write!(f, "Could not deserialize LLM response ({}): {}", error, raw),
+            LlmError::BudgetExceeded(msg) => write!(f, "Run budget exceeded: {}", msg),
+            LlmError::UnmatchedCassette(msg) => write!(f, "Unmatched request in cassette replay: {}", msg),
+            LlmError::ContextLengthExceeded { model, prompt_tokens, context_tokens } => write!(
+                f, "Prompt of about {} tokens does not fit the {} token context window of {}", prompt_tokens, context_tokens, model
+            ),
+            LlmError::ToolCalling(msg) => write!(f, "Tool calling failed: {}", msg),
+            LlmError::Cancelled => write!(f, "Request cancelled before the LLM answered"),
+            LlmError::VisionUnsupported { provider, model } => write!(
+                f, "{} ({}) does not accept images, pick a vision model such as GPT-4o or Claude, or set \"vision\": true for {} under \"models\" in acadia.json", provider, model, model
+            ),
+            LlmError::Io(msg) => write!(f, "File error: {}", msg)
+        }
+    }
+}
+
+impl LlmError {
+
+    /// Transient failures worth sending again, everything else fails the request right away
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LlmError::Transport(_) | LlmError::RateLimited { .. } => true,
+            // 529 is Anthropic's "overloaded"
+            LlmError::HttpStatus { status, .. } => matches!(status, 408 | 409 | 500 | 502 | 503 | 504 | 529),
+            LlmError::MissingConfig(_)
+            | LlmError::MalformedResponse(_)
+            | LlmError::Decode { .. }
+            | LlmError::BudgetExceeded(_)
+            | LlmError::UnmatchedCassette(_)
+            | LlmError::ContextLengthExceeded { .. }
+            | LlmError::ToolCalling(_)
+            | LlmError::Cancelled
+            | LlmError::VisionUnsupported { .. }
+            | LlmError::Io(_) => false
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        LlmError::Transport(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_error_classification() {
+        assert!(LlmError::Transport("connection reset".to_string()).is_retryable());
+        assert!(LlmError::RateLimited { retry_after: None, body: String::new() }.is_retryable());
+        assert!(LlmError::HttpStatus { status: 502, body: String::new() }.is_retryable());
+        assert!(!LlmError::HttpStatus { status: 401, body: String::new() }.is_retryable());
+        assert!(!LlmError::MissingConfig("OPEN_AI_KEY".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn test_llm_error_display() {
+        let missing = LlmError::MissingConfig("OPEN_AI_KEY is not set".to_string());
+        let status = LlmError::HttpStatus { status: 500, body: "upstream failure".to_string() };
+        let limited = LlmError::RateLimited { retry_after: Some(Duration::from_secs(20)), body: "Rate limit reached for gpt-4o".to_string() };
+
+        assert_eq!(missing.to_string(), "LLM configuration error: OPEN_AI_KEY is not set");
+        assert_eq!(status.to_string(), "LLM service responded with status 500: upstream failure");
+        assert_eq!(limited.to_string(), "LLM service rate limited the request, retry after 20s: Rate limit reached for gpt-4o");
+    }
+}
@@ -1,2 +1,8 @@
+pub mod config;
+pub mod generation;
 pub mod llm;
-pub mod project;
\ No newline at end of file
+pub mod llm_error;
+pub mod model_capabilities;
+pub mod pricing;
+pub mod project;
+pub mod tools;
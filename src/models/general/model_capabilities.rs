@@ -0,0 +1,81 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Token limits of a model and whether it reads images
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ModelCapability {
+    /// Prompt and answer together
+    pub context_tokens: u64,
+    pub max_output_tokens: u32,
+    /// Average characters per token of the model's tokenizer, used to estimate prompt sizes
+    #[serde(default = "default_chars_per_token")]
+    pub chars_per_token: f64,
+    /// Accepts images in its messages
+    #[serde(default)]
+    pub vision: bool
+}
+
+fn default_chars_per_token() -> f64 {
+    4.0
+}
+
+/// Capabilities keyed by model name. Entries from the config file take precedence over the built in list.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct CapabilityTable {
+    overrides: HashMap<String, ModelCapability>
+}
+
+/// Built in limits of the models Acadia ships providers for
+fn default_capabilities() -> Vec<(&'static str, ModelCapability)> {
+    vec![
+        ("gpt-4o", ModelCapability { context_tokens: 128_000, max_output_tokens: 16_384, chars_per_token: 4.0, vision: true }),
+        ("claude-3-5", ModelCapability { context_tokens: 200_000, max_output_tokens: 8_192, chars_per_token: 3.5, vision: true }),
+        ("claude-3-opus", ModelCapability { context_tokens: 200_000, max_output_tokens: 4_096, chars_per_token: 3.5, vision: true }),
+        ("Llama3", ModelCapability { context_tokens: 8_192, max_output_tokens: 2_048, chars_per_token: 3.8, vision: false }),
+        ("llama3", ModelCapability { context_tokens: 8_192, max_output_tokens: 2_048, chars_per_token: 3.8, vision: false })
+    ]
+}
+
+impl CapabilityTable {
+
+    /// Exact match first, then the longest known prefix, the same lookup the price table uses
+    pub fn capability(&self, model: &str) -> Option<ModelCapability> {
+        if let Some(capability) = self.overrides.get(model) {
+            return Some(*capability);
+        }
+
+        // Overrides go last so they win ties, max_by_key keeps the last maximum
+        let mut candidates: Vec<(&str, ModelCapability)> = default_capabilities();
+        candidates.extend(self.overrides.iter().map(|(name, capability)| (name.as_str(), *capability)));
+
+        candidates
+            .into_iter()
+            .filter(|(name, _)| model.starts_with(name))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, capability)| capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_capability_lookup() {
+        let table: CapabilityTable = serde_json::from_str(r#"{
+            "llama3:70b": { "context_tokens": 32000, "max_output_tokens": 4096 },
+            "llava": { "context_tokens": 4096, "max_output_tokens": 1024, "vision": true }
+        }"#).unwrap();
+
+        assert_eq!(table.capability("gpt-4o-mini").unwrap().context_tokens, 128_000);
+        assert_eq!(table.capability("claude-3-5-sonnet-latest").unwrap().max_output_tokens, 8_192);
+        assert_eq!(table.capability("llama3:70b-instruct").unwrap().context_tokens, 32_000);
+        assert_eq!(table.capability("llama3:70b-instruct").unwrap().chars_per_token, 4.0);
+        assert!(table.capability("mystery-model").is_none());
+        assert!(table.capability("gpt-4o-mini").unwrap().vision);
+        assert!(!table.capability("llama3:70b-instruct").unwrap().vision);
+        assert!(table.capability("llava:13b").unwrap().vision);
+    }
+}
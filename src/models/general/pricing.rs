@@ -0,0 +1,87 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::llm::TokenUsage;
+
+/// USD price per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ModelPrice {
+    pub prompt_per_million: f64,
+    pub completion_per_million: f64
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt_per_million
+            + usage.completion_tokens as f64 * self.completion_per_million) / 1_000_000.0
+    }
+}
+
+/// Prices keyed by model name. Entries from the config file take precedence over the built in list.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct PriceTable {
+    overrides: HashMap<String, ModelPrice>
+}
+
+/// Built in list prices, good enough for an estimate. Local models are free.
+fn default_prices() -> Vec<(&'static str, ModelPrice)> {
+    vec![
+        ("gpt-4o-mini", ModelPrice { prompt_per_million: 0.15, completion_per_million: 0.60 }),
+        ("gpt-4o", ModelPrice { prompt_per_million: 2.50, completion_per_million: 10.00 }),
+        ("claude-3-5-haiku", ModelPrice { prompt_per_million: 0.80, completion_per_million: 4.00 }),
+        ("claude-3-5-sonnet", ModelPrice { prompt_per_million: 3.00, completion_per_million: 15.00 }),
+        ("claude-3-opus", ModelPrice { prompt_per_million: 15.00, completion_per_million: 75.00 }),
+        ("Llama3", ModelPrice { prompt_per_million: 0.0, completion_per_million: 0.0 }),
+        ("llama3", ModelPrice { prompt_per_million: 0.0, completion_per_million: 0.0 })
+    ]
+}
+
+impl PriceTable {
+
+    /// Exact match first, then the longest known prefix so dated snapshots like `gpt-4o-2024-08-06` resolve
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        if let Some(price) = self.overrides.get(model) {
+            return Some(*price);
+        }
+
+        // Overrides go last so they win ties, max_by_key keeps the last maximum
+        let mut candidates: Vec<(&str, ModelPrice)> = default_prices();
+        candidates.extend(self.overrides.iter().map(|(name, price)| (name.as_str(), *price)));
+
+        candidates
+            .into_iter()
+            .filter(|(name, _)| model.starts_with(name))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| price)
+    }
+
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.price(model).map(|price| price.cost(usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_price_prefix_match() {
+        let table = PriceTable::default();
+
+        assert_eq!(table.price("gpt-4o-2024-08-06"), table.price("gpt-4o"));
+        assert_eq!(table.price("gpt-4o-mini-2024-07-18").unwrap().prompt_per_million, 0.15);
+        assert!(table.price("some-unknown-model").is_none());
+    }
+
+    #[test]
+    fn test_config_overrides_defaults() {
+        let table: PriceTable = serde_json::from_str(r#"{
+            "gpt-4o": { "prompt_per_million": 5.0, "completion_per_million": 15.0 }
+        }"#).unwrap();
+        let usage = TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 100_000 };
+
+        assert_eq!(table.cost("gpt-4o-2024-05-13", &usage), Some(6.5));
+    }
+}
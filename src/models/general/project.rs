@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ProjectSpec {
     pub project_description: Option<String>,
     pub backend_code: Option<String>,
@@ -81,7 +81,9 @@ pub struct UserInputs {
     pub project_focus: Option<String>,
     pub backend_language: Option<String>,
     pub frontend_language: Option<String>,
-    pub llm_model: Option<String>
+    pub llm_model: Option<String>,
+    /// UI mockups or architecture sketches shown to the Manager and Solutions Architect
+    pub image_paths: Vec<String>
 }
 
 
@@ -92,7 +94,8 @@ impl UserInputs {
             project_focus: None,
             backend_language: None,
             frontend_language: None,
-            llm_model: None
+            llm_model: None,
+            image_paths: Vec::new()
         }
     }
 }
\ No newline at end of file
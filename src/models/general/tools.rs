@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::llm::{LLMCompletion, Message};
+
+/// A tool the model may call, `parameters` is the JSON Schema of its arguments object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value
+}
+
+/// A call the model asked for, the result is sent back under the same `id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value
+}
+
+/// One entry of a conversation with tools. Providers turn these into their own message shapes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolTurn {
+    Message(Message),
+    /// Assistant answer that asked for tool calls, sent back so the model sees what it called
+    ToolCalls { content: String, calls: Vec<ToolCall> },
+    ToolResult { call_id: String, name: String, content: String }
+}
+
+impl ToolTurn {
+
+    /// Plain message standing in for the turn, used to estimate the size of a conversation with tools
+    pub fn to_message(&self) -> Message {
+        match self {
+            ToolTurn::Message(message) => message.clone(),
+            ToolTurn::ToolCalls { content, calls } => Message {
+                role: "assistant".to_string(),
+                content: calls.iter().fold(content.clone(), |text, call| format!("{} {}({})", text, call.name, call.arguments)),
+                images: Vec::new()
+            },
+            ToolTurn::ToolResult { content, .. } => Message { role: "user".to_string(), content: content.clone(), images: Vec::new() }
+        }
+    }
+}
+
+impl From<Message> for ToolTurn {
+    fn from(message: Message) -> Self {
+        ToolTurn::Message(message)
+    }
+}
+
+/// Answer to a request with tools. When `tool_calls` is empty the content is the final answer.
+#[derive(Debug, Clone)]
+pub struct ToolCompletion {
+    pub completion: LLMCompletion,
+    pub tool_calls: Vec<ToolCall>
+}
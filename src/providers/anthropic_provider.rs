@@ -0,0 +1,377 @@
+use async_trait::async_trait;
+use dotenv::dotenv;
+use reqwest::{header::{HeaderMap, HeaderValue}, Method, RequestBuilder, Response};
+use serde_json::{json, Value};
+use std::env;
+
+use crate::{models::general::{generation::GenerationParams, llm::{anthropic_message_json, AnthropicRequestBody, AnthropicResponse, AnthropicStreamEvent, LLMCompletion, LLMStreamEvent, Message}, llm_error::LlmError, tools::{ToolCall, ToolCompletion, ToolDefinition, ToolTurn}}, utils::http_client::shared_client};
+
+use super::{provider_registry::CLAUDE, provider_traits::{LLMStream, LlmProvider}, provider_utils::{header_value, ping_endpoint, read_json, required_env, warn_unsupported_params}, sse::sse_delta_stream};
+
+const ANTHROPIC_URL: &str = "https://api.anthropic.com/v1/messages";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const ANTHROPIC_MODEL: &str = "claude-3-5-sonnet-latest";
+const ANTHROPIC_MAX_TOKENS: u32 = 4096;
+
+/// Anthropic Messages API. Anything not set explicitly is read from the .env file
+/// (`ANTHROPIC_URL`, `ANTHROPIC_API_KEY`, `ANTHROPIC_MODEL`) when the request is made.
+#[derive(Debug, Default)]
+pub struct AnthropicProvider {
+    url: Option<String>,
+    api_key: Option<String>,
+    model: Option<String>
+}
+
+impl AnthropicProvider {
+    pub fn new() -> Self {
+        AnthropicProvider::default()
+    }
+
+    /// Provider with every setting given up front, used to point tests at a local server
+    #[cfg(test)]
+    pub fn with_settings(url: &str, api_key: &str, model: &str) -> Self {
+        AnthropicProvider {
+            url: Some(url.to_string()),
+            api_key: Some(api_key.to_string()),
+            model: Some(model.to_string())
+        }
+    }
+
+    /// Request to the Messages API url with the auth headers
+    fn request(&self, method: Method) -> Result<RequestBuilder, LlmError> {
+        dotenv().ok();
+
+        let key: String = match &self.api_key {
+            Some(key) => key.clone(),
+            None => required_env("ANTHROPIC_API_KEY")?
+        };
+        let url: String = self.url.clone()
+            .or_else(|| env::var("ANTHROPIC_URL").ok())
+            .unwrap_or_else(|| ANTHROPIC_URL.to_string());
+
+        let mut header_map = HeaderMap::new();
+        header_map.insert("x-api-key", header_value("ANTHROPIC_API_KEY", &key)?);
+        header_map.insert("anthropic-version", HeaderValue::from_static(ANTHROPIC_VERSION));
+
+        Ok(shared_client()?.request(method, &url).headers(header_map))
+    }
+
+    fn request_body(&self, messages: Vec<Message>, params: &GenerationParams, stream: bool) -> AnthropicRequestBody {
+        // The Messages API has no seed and no JSON mode
+        warn_unsupported_params(self.name(), params, &["temperature", "max_tokens", "stop"]);
+
+        let (system, conversation) = split_system_prompt(messages);
+        AnthropicRequestBody {
+            model: LlmProvider::model(self),
+            max_tokens: params.max_tokens.unwrap_or(ANTHROPIC_MAX_TOKENS),
+            system,
+            messages: conversation,
+            stream,
+            temperature: params.temperature,
+            stop_sequences: params.stop.clone()
+        }
+    }
+
+    async fn post_messages(&self, messages: Vec<Message>, params: &GenerationParams, stream: bool) -> Result<Response, LlmError> {
+        let request = self.request(Method::POST)?;
+        let llm_req_body = self.request_body(messages, params, stream);
+
+        let response = request
+            .json(&llm_req_body)
+            .send()
+            .await?;
+
+        Ok(response)
+    }
+}
+
+/// Messages API body with `tools`. Tool calls are `tool_use` blocks of the assistant, results are
+/// `tool_result` blocks of the user, consecutive results share one user message.
+fn anthropic_tool_body(body: AnthropicRequestBody, conversation: &[ToolTurn], tools: &[ToolDefinition]) -> Result<Value, LlmError> {
+    let mut messages: Vec<Value> = Vec::new();
+    for turn in conversation {
+        match turn {
+            // System messages were already moved into the top level `system` field
+            ToolTurn::Message(message) if message.role == "system" => {},
+            ToolTurn::Message(message) => messages.push(anthropic_message_json(message)),
+            ToolTurn::ToolCalls { content, calls } => {
+                let text = (!content.is_empty()).then(|| json!({ "type": "text", "text": content }));
+                let blocks: Vec<Value> = text.into_iter()
+                    .chain(calls.iter().map(|call| json!({ "type": "tool_use", "id": call.id, "name": call.name, "input": call.arguments })))
+                    .collect();
+                messages.push(json!({ "role": "assistant", "content": blocks }));
+            },
+            ToolTurn::ToolResult { call_id, content, .. } => {
+                let block = json!({ "type": "tool_result", "tool_use_id": call_id, "content": content });
+                match messages.last_mut() {
+                    Some(last) if last["role"] == "user" && last["content"].is_array() => last["content"].as_array_mut().unwrap().push(block),
+                    _ => messages.push(json!({ "role": "user", "content": [block] }))
+                }
+            }
+        }
+    }
+
+    let mut body = serde_json::to_value(body).map_err(|e| LlmError::MalformedResponse(format!("could not build tool request: {}", e)))?;
+    body["messages"] = Value::Array(messages);
+    body["tools"] = tools.iter().map(|tool| json!({ "name": tool.name, "description": tool.description, "input_schema": tool.parameters })).collect();
+    Ok(body)
+}
+
+/// Joined text blocks and the `tool_use` blocks of an answer
+fn anthropic_tool_completion(res: AnthropicResponse, provider: &str) -> Result<ToolCompletion, LlmError> {
+    let mut content = String::new();
+    let mut tool_calls: Vec<ToolCall> = Vec::new();
+    for block in res.content {
+        match block.block_type.as_str() {
+            "text" => content.push_str(&block.text.unwrap_or_default()),
+            "tool_use" => match (block.id, block.name) {
+                (Some(id), Some(name)) => tool_calls.push(ToolCall { id, name, arguments: block.input.unwrap_or_else(|| json!({})) }),
+                _ => return Err(LlmError::MalformedResponse("tool_use block without id or name".to_string()))
+            },
+            _ => {}
+        }
+    }
+
+    Ok(ToolCompletion {
+        completion: LLMCompletion {
+            content,
+            provider: provider.to_string(),
+            model: res.model,
+            usage: res.usage.map(|usage| usage.into())
+        },
+        tool_calls
+    })
+}
+
+/// Pulls every system message out into the top level `system` field
+fn split_system_prompt(messages: Vec<Message>) -> (Option<String>, Vec<Message>) {
+    let (system, conversation): (Vec<Message>, Vec<Message>) = messages
+        .into_iter()
+        .partition(|msg| msg.role == "system");
+
+    let system_prompt = if system.is_empty() {
+        None
+    } else {
+        Some(system.into_iter().map(|msg| msg.content).collect::<Vec<String>>().join("\n"))
+    };
+
+    (system_prompt, conversation)
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+
+    fn name(&self) -> &str {
+        CLAUDE
+    }
+
+    fn model(&self) -> String {
+        dotenv().ok();
+        self.model.clone()
+            .or_else(|| env::var("ANTHROPIC_MODEL").ok())
+            .unwrap_or_else(|| ANTHROPIC_MODEL.to_string())
+    }
+
+    fn check_config(&self) -> Result<(), LlmError> {
+        self.request(Method::POST).map(|_| ())
+    }
+
+    async fn ping(&self) -> Result<(), LlmError> {
+        ping_endpoint(self.request(Method::GET)?).await
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let response = self.post_messages(messages, params, false).await?;
+        let res: AnthropicResponse = read_json(response).await?;
+
+        // Only text blocks carry the answer
+        let content: String = res.content
+            .iter()
+            .filter(|block| block.block_type == "text")
+            .filter_map(|block| block.text.clone())
+            .collect();
+
+        Ok(LLMCompletion {
+            content,
+            provider: self.name().to_string(),
+            model: res.model,
+            usage: res.usage.map(|usage| usage.into())
+        })
+    }
+
+    async fn stream_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMStream, LlmError> {
+        let response = self.post_messages(messages, params, true).await?;
+        sse_delta_stream(response, parse_anthropic_event).await
+    }
+
+    async fn send_with_tools(&self, conversation: Vec<ToolTurn>, tools: &[ToolDefinition], params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+        let request = self.request(Method::POST)?;
+        let system_messages: Vec<Message> = conversation.iter()
+            .filter_map(|turn| match turn {
+                ToolTurn::Message(message) if message.role == "system" => Some(message.clone()),
+                _ => None
+            })
+            .collect();
+        let body = anthropic_tool_body(self.request_body(system_messages, params, false), &conversation, tools)?;
+
+        let response = request
+            .json(&body)
+            .send()
+            .await?;
+
+        anthropic_tool_completion(read_json(response).await?, self.name())
+    }
+}
+
+/// Text or usage of one streamed Messages API event
+fn parse_anthropic_event(data: &str) -> Option<LLMStreamEvent> {
+    let event: AnthropicStreamEvent = serde_json::from_str(data).ok()?;
+    match event.event_type.as_str() {
+        "content_block_delta" => event.delta?.text.map(LLMStreamEvent::Delta),
+        "message_start" => event.message?.usage.map(|usage| LLMStreamEvent::Usage(usage.into())),
+        "message_delta" => event.usage.map(|usage| LLMStreamEvent::Usage(usage.into())),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+    use wiremock::{matchers::{body_partial_json, header, method, path}, Mock, MockServer, ResponseTemplate};
+
+    use crate::models::general::llm::TokenUsage;
+
+    use super::*;
+
+    #[test]
+    fn test_split_system_prompt() {
+        let messages = vec![
+            Message { role: "system".to_string(), content: "You are a function printer".to_string(), images: Vec::new() },
+            Message { role: "user".to_string(), content: "Build a todo app".to_string(), images: Vec::new() }
+        ];
+
+        let (system, conversation) = split_system_prompt(messages);
+
+        assert_eq!(system, Some("You are a function printer".to_string()));
+        assert_eq!(conversation.len(), 1);
+        assert_eq!(conversation[0].role, "user");
+    }
+
+    #[test]
+    fn test_parse_anthropic_event() {
+        let delta = r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#;
+        let start = r#"{"type":"message_start","message":{"id":"msg_01","usage":{"input_tokens":25,"output_tokens":1}}}"#;
+        let end = r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":15}}"#;
+        let ping = r#"{"type":"ping"}"#;
+
+        assert_eq!(parse_anthropic_event(delta), Some(LLMStreamEvent::Delta("Hello".to_string())));
+        assert_eq!(parse_anthropic_event(start), Some(LLMStreamEvent::Usage(TokenUsage { prompt_tokens: 25, completion_tokens: 1 })));
+        assert_eq!(parse_anthropic_event(end), Some(LLMStreamEvent::Usage(TokenUsage { prompt_tokens: 0, completion_tokens: 15 })));
+        assert_eq!(parse_anthropic_event(ping), None);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_tool_use() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "system": "You are a backend developer",
+                "tools": [{ "name": "check_url", "description": "GET a url", "input_schema": { "type": "object" } }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "claude-test",
+                "content": [
+                    { "type": "text", "text": "Checking the url" },
+                    { "type": "tool_use", "id": "toolu_01", "name": "check_url", "input": { "url": "https://example.com" } }
+                ],
+                "stop_reason": "tool_use",
+                "usage": { "input_tokens": 60, "output_tokens": 20 }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = AnthropicProvider::with_settings(&format!("{}/v1/messages", server.uri()), "test-key", "claude-test");
+        let conversation = vec![
+            ToolTurn::Message(Message { role: "system".to_string(), content: "You are a backend developer".to_string(), images: Vec::new() }),
+            ToolTurn::Message(Message { role: "user".to_string(), content: "Is https://example.com up?".to_string(), images: Vec::new() }),
+            ToolTurn::ToolCalls {
+                content: String::new(),
+                calls: vec![
+                    ToolCall { id: "toolu_00".to_string(), name: "check_url".to_string(), arguments: json!({ "url": "https://a.com" }) },
+                    ToolCall { id: "toolu_02".to_string(), name: "check_url".to_string(), arguments: json!({ "url": "https://b.com" }) }
+                ]
+            },
+            ToolTurn::ToolResult { call_id: "toolu_00".to_string(), name: "check_url".to_string(), content: "200".to_string() },
+            ToolTurn::ToolResult { call_id: "toolu_02".to_string(), name: "check_url".to_string(), content: "404".to_string() }
+        ];
+        let tools = vec![ToolDefinition { name: "check_url".to_string(), description: "GET a url".to_string(), parameters: json!({ "type": "object" }) }];
+
+        let completion = provider.send_with_tools(conversation, &tools, &GenerationParams::default()).await.unwrap();
+        let body: serde_json::Value = server.received_requests().await.unwrap()[0].body_json().unwrap();
+
+        assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(body["messages"][1]["content"][1]["type"], "tool_use");
+        assert_eq!(body["messages"][2]["content"][1], json!({ "type": "tool_result", "tool_use_id": "toolu_02", "content": "404" }));
+        assert_eq!(completion.completion.content, "Checking the url");
+        assert_eq!(completion.tool_calls, vec![ToolCall { id: "toolu_01".to_string(), name: "check_url".to_string(), arguments: json!({ "url": "https://example.com" }) }]);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_messages_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(header("x-api-key", "test-key"))
+            .and(header("anthropic-version", ANTHROPIC_VERSION))
+            .and(body_partial_json(json!({
+                "model": "claude-test",
+                "system": "You are a function printer",
+                "messages": [{ "role": "user", "content": "Build a todo app" }],
+                "max_tokens": 1000,
+                "temperature": 0.0
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "msg_01",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-test",
+                "content": [
+                    { "type": "text", "text": "build a website that " },
+                    { "type": "text", "text": "tracks todo items" }
+                ],
+                "stop_reason": "end_turn",
+                "usage": { "input_tokens": 40, "output_tokens": 9 }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = AnthropicProvider::with_settings(
+            &format!("{}/v1/messages", server.uri()),
+            "test-key",
+            "claude-test"
+        );
+        let messages = vec![
+            Message { role: "system".to_string(), content: "You are a function printer".to_string(), images: Vec::new() },
+            Message { role: "user".to_string(), content: "Build a todo app".to_string(), images: Vec::new() }
+        ];
+
+        let params = GenerationParams { temperature: Some(0.0), max_tokens: Some(1000), seed: Some(7), ..GenerationParams::default() };
+
+        let completion = provider.send_messages(messages, &params).await.unwrap();
+        let body: serde_json::Value = server.received_requests().await.unwrap()[0].body_json().unwrap();
+
+        assert!(body.get("seed").is_none());
+        assert_eq!(completion.content, "build a website that tracks todo items");
+        assert_eq!(completion.provider, CLAUDE);
+        assert_eq!(completion.model, "claude-test");
+        assert_eq!(completion.usage, Some(TokenUsage { prompt_tokens: 40, completion_tokens: 9 }));
+    }
+}
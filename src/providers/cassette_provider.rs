@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use super::provider_traits::{LlmProvider, ProviderHandle};
+use crate::{models::general::{generation::GenerationParams, llm::{LLMCompletion, Message}, llm_error::LlmError, tools::{ToolCompletion, ToolDefinition, ToolTurn}}, utils::response_cache::ResponseCache};
+
+/// Directory cassettes are read from and written to unless `ACADIA_CASSETTE_DIR` is set
+pub const DEFAULT_CASSETTE_DIR: &str = "cassettes";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    /// Forward every request to the real provider and save the answer
+    Record,
+    /// Answer from saved cassettes only, requests without a cassette fail
+    Replay
+}
+
+impl CassetteMode {
+    /// Reads `ACADIA_CASSETTE=record|replay`, None when unset
+    pub fn from_env() -> Option<Self> {
+        match env::var("ACADIA_CASSETTE").ok()?.to_lowercase().as_str() {
+            "record" => Some(CassetteMode::Record),
+            "replay" => Some(CassetteMode::Replay),
+            _ => None
+        }
+    }
+}
+
+/// One recorded request/response pair
+#[derive(Debug, Serialize, Deserialize)]
+struct Interaction {
+    provider: String,
+    messages: Vec<Message>,
+    completion: LLMCompletion
+}
+
+/// Wraps a provider to record its answers to disk or to replay them without network access.
+/// Each interaction is stored in its own file named after the hash of the request,
+/// so tests running in parallel never write to the same cassette.
+#[derive(Debug)]
+pub struct CassetteProvider {
+    inner: ProviderHandle,
+    mode: CassetteMode,
+    dir: PathBuf
+}
+
+impl CassetteProvider {
+    pub fn new(inner: ProviderHandle, mode: CassetteMode, dir: &str) -> Self {
+        CassetteProvider {
+            inner,
+            mode,
+            dir: PathBuf::from(dir)
+        }
+    }
+
+    /// Wraps `inner` in `mode`, cassettes live in `ACADIA_CASSETTE_DIR` when it is set
+    pub fn wrap(inner: ProviderHandle, mode: Option<CassetteMode>) -> ProviderHandle {
+        match mode {
+            Some(mode) => {
+                let dir = env::var("ACADIA_CASSETTE_DIR").unwrap_or_else(|_| DEFAULT_CASSETTE_DIR.to_string());
+                Arc::new(CassetteProvider::new(inner, mode, &dir))
+            },
+            None => inner
+        }
+    }
+
+    /// The model is left out of the key so cassettes replay regardless of `LLM_MODEL`
+    fn cassette_path(&self, messages: &[Message], params: &GenerationParams) -> PathBuf {
+        let key = ResponseCache::key(self.inner.name(), "", messages, params);
+        self.dir.join(self.inner.name()).join(format!("{}.json", key))
+    }
+
+    fn replay(&self, messages: &[Message], params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let path = self.cassette_path(messages, params);
+        let contents = fs::read_to_string(&path).map_err(|_| LlmError::UnmatchedCassette(format!(
+            "no {} cassette at {} for a request starting with {:?}",
+            self.inner.name(),
+            path.display(),
+            messages.first().map(|msg| msg.content.chars().take(80).collect::<String>()).unwrap_or_default()
+        )))?;
+
+        let interaction: Interaction = serde_json::from_str(&contents)
+            .map_err(|e| LlmError::UnmatchedCassette(format!("{} is not a valid cassette: {}", path.display(), e)))?;
+        Ok(interaction.completion)
+    }
+
+    async fn record(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let path = self.cassette_path(&messages, params);
+        let completion = self.inner.send_messages(messages.clone(), params).await?;
+
+        let interaction = Interaction {
+            provider: self.inner.name().to_string(),
+            messages,
+            completion
+        };
+        let write_cassette = || -> std::io::Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, serde_json::to_string_pretty(&interaction)?)
+        };
+        write_cassette().map_err(|e| LlmError::Io(format!("could not write cassette {}: {}", path.display(), e)))?;
+
+        Ok(interaction.completion)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for CassetteProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn model(&self) -> String {
+        self.inner.model()
+    }
+
+    fn supports_vision(&self) -> bool {
+        self.inner.supports_vision()
+    }
+
+    /// Tool conversations are not recorded, so replaying never offers tools
+    fn supports_tools(&self) -> bool {
+        match self.mode {
+            CassetteMode::Record => self.inner.supports_tools(),
+            CassetteMode::Replay => false
+        }
+    }
+
+    /// Replaying needs no credentials
+    fn check_config(&self) -> Result<(), LlmError> {
+        match self.mode {
+            CassetteMode::Record => self.inner.check_config(),
+            CassetteMode::Replay => Ok(())
+        }
+    }
+
+    async fn ping(&self) -> Result<(), LlmError> {
+        match self.mode {
+            CassetteMode::Record => self.inner.ping().await,
+            CassetteMode::Replay => Ok(())
+        }
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        match self.mode {
+            CassetteMode::Record => self.record(messages, params).await,
+            CassetteMode::Replay => self.replay(&messages, params)
+        }
+    }
+
+    /// Tool conversations are not recorded, they go straight to the real provider when recording
+    async fn send_with_tools(&self, conversation: Vec<ToolTurn>, tools: &[ToolDefinition], params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+        match self.mode {
+            CassetteMode::Record => self.inner.send_with_tools(conversation, tools, params).await,
+            CassetteMode::Replay => Err(LlmError::UnmatchedCassette(format!("tool calling requests to {} are not recorded", self.inner.name())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct CountingProvider {
+        calls: AtomicUsize
+    }
+
+    #[async_trait]
+    impl LlmProvider for CountingProvider {
+        fn name(&self) -> &str {
+            "Counting"
+        }
+
+        fn model(&self) -> String {
+            "counting-model".to_string()
+        }
+
+        async fn send_messages(&self, messages: Vec<Message>, _params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(LLMCompletion {
+                content: format!("answer {} to {}", call, messages[0].content),
+                provider: self.name().to_string(),
+                model: self.model(),
+                usage: None
+            })
+        }
+    }
+
+    fn user_message(content: &str) -> Vec<Message> {
+        vec![Message { role: "user".to_string(), content: content.to_string(), images: Vec::new() }]
+    }
+
+    fn test_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("acadia_test_cassettes_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = test_dir("record_replay");
+        let inner = Arc::new(CountingProvider::default());
+
+        let recorder = CassetteProvider::new(inner.clone(), CassetteMode::Record, &dir);
+        let recorded = recorder.send_messages(user_message("Build a todo app"), &GenerationParams::default()).await.unwrap();
+
+        let player = CassetteProvider::new(inner.clone(), CassetteMode::Replay, &dir);
+        let replayed = player.send_messages(user_message("Build a todo app"), &GenerationParams::default()).await.unwrap();
+
+        assert_eq!(replayed.content, recorded.content);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_fails_on_unmatched_request() {
+        let dir = test_dir("unmatched");
+        let player = CassetteProvider::new(Arc::new(CountingProvider::default()), CassetteMode::Replay, &dir);
+
+        let res = player.send_messages(user_message("Build a blog"), &GenerationParams::default()).await;
+
+        assert!(matches!(res, Err(LlmError::UnmatchedCassette(_))));
+    }
+}
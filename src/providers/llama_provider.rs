@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+
+use crate::{models::general::{generation::GenerationParams, llm::{LLMCompletion, LLMRequestBody, LLMResponse, Message}, llm_error::LlmError}, utils::http_client::shared_client};
+
+use super::{provider_registry::LLAMA3, provider_traits::LlmProvider, provider_utils::{ping_endpoint, read_json, required_env, warn_unsupported_params}};
+
+/// Locally hosted Llama3 server answering with `{ "generated_text": ... }`
+#[derive(Debug, Default)]
+pub struct LlamaProvider;
+
+impl LlamaProvider {
+    pub fn new() -> Self {
+        LlamaProvider
+    }
+}
+
+#[async_trait]
+impl LlmProvider for LlamaProvider {
+
+    fn name(&self) -> &str {
+        LLAMA3
+    }
+
+    fn model(&self) -> String {
+        "Llama3".to_string()
+    }
+
+    fn check_config(&self) -> Result<(), LlmError> {
+        required_env("LLM_URL").map(|_| ())
+    }
+
+    async fn ping(&self) -> Result<(), LlmError> {
+        ping_endpoint(shared_client()?.get(required_env("LLM_URL")?)).await
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let url: String = required_env("LLM_URL")?;
+        warn_unsupported_params(self.name(), params, &[]);
+
+        let llm_req_body = LLMRequestBody::new(messages, self.model());
+        let response = shared_client()?
+            .post(&url)
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .json(&llm_req_body)
+            .send()
+            .await?;
+        let res: LLMResponse = read_json(response).await?;
+
+        Ok(LLMCompletion {
+            content: res.generated_text,
+            provider: self.name().to_string(),
+            model: self.model(),
+            usage: None
+        })
+    }
+}
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use std::{collections::{HashMap, VecDeque}, sync::Mutex};
+
+use super::provider_traits::LlmProvider;
+use crate::models::general::{generation::GenerationParams, llm::{LLMCompletion, Message, TokenUsage}, llm_error::LlmError};
+
+/// A request the mock received, `ai_function` is the `function_string!` name found in the system prompt
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub ai_function: Option<String>,
+    pub messages: Vec<Message>,
+    pub params: GenerationParams
+}
+
+#[derive(Debug, Default)]
+struct MockScript {
+    replies: HashMap<String, VecDeque<Result<String, LlmError>>>,
+    requests: Vec<MockRequest>
+}
+
+/// In-process provider for unit tests, answers are scripted per AI function.
+/// Scripted replies are handed out in order, the last one keeps being returned once the others are used up.
+#[derive(Debug, Default)]
+pub struct MockProvider {
+    script: Mutex<MockScript>
+}
+
+/// Name of the AI function a prompt was built from, taken from the `FUNCTION: pub fn <name>` system prompt
+pub fn ai_function_name(messages: &[Message]) -> Option<String> {
+    messages.iter().find_map(|message| {
+        let function = message.content.split_once("FUNCTION:")?.1;
+        let name = function.split_once("fn ")?.1.trim_start();
+        let name: String = name.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+        (!name.is_empty()).then_some(name)
+    })
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        MockProvider::default()
+    }
+
+    /// Queues `response` as the next answer to prompts built from `ai_function`
+    pub fn respond(&self, ai_function: &str, response: &str) -> &Self {
+        self.push(ai_function, Ok(response.to_string()))
+    }
+
+    /// Queues `error` as the next answer to prompts built from `ai_function`
+    pub fn fail(&self, ai_function: &str, error: LlmError) -> &Self {
+        self.push(ai_function, Err(error))
+    }
+
+    fn push(&self, ai_function: &str, reply: Result<String, LlmError>) -> &Self {
+        self.script.lock().unwrap().replies.entry(ai_function.to_string()).or_default().push_back(reply);
+        self
+    }
+
+    /// Every request received so far, oldest first
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.script.lock().unwrap().requests.clone()
+    }
+
+    /// Requests whose prompt was built from `ai_function`
+    pub fn requests_for(&self, ai_function: &str) -> Vec<MockRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.ai_function.as_deref() == Some(ai_function))
+            .collect()
+    }
+
+    /// Panics unless some prompt built from `ai_function` contains `expected`
+    pub fn assert_prompt_contains(&self, ai_function: &str, expected: &str) {
+        let requests = self.requests_for(ai_function);
+        assert!(
+            requests.iter().any(|request| request.messages.iter().any(|message| message.content.contains(expected))),
+            "No {} prompt contains {:?}, prompts sent: {:#?}", ai_function, expected, requests
+        );
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        "Mock"
+    }
+
+    fn model(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn supports_vision(&self) -> bool {
+        true
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let ai_function = ai_function_name(&messages);
+        let mut script = self.script.lock().unwrap();
+        script.requests.push(MockRequest { ai_function: ai_function.clone(), messages, params: params.clone() });
+
+        let function_name = ai_function.unwrap_or_default();
+        let replies = script.replies.entry(function_name.clone()).or_default();
+        let reply = match replies.front() {
+            None => Err(LlmError::MalformedResponse(format!("MockProvider has no reply scripted for {:?}", function_name))),
+            Some(Ok(content)) if replies.len() == 1 => Ok(content.clone()),
+            Some(_) => replies.pop_front().unwrap()
+        };
+
+        reply.map(|content| LLMCompletion {
+            usage: Some(TokenUsage { prompt_tokens: 10, completion_tokens: content.len() as u64 }),
+            content,
+            provider: self.name().to_string(),
+            model: self.model()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::ai_functions::ai_functions::{convert_user_input_to_goal, print_site_urls};
+
+    use super::*;
+
+    fn function_prompt(ai_func: fn(&str) -> &'static str) -> Vec<Message> {
+        vec![
+            Message { role: "system".to_string(), content: format!("FUNCTION: {}", ai_func("Build a todo app")), images: Vec::new() },
+            Message { role: "user".to_string(), content: "Here is the input of the function: Build a todo app".to_string(), images: Vec::new() }
+        ]
+    }
+
+    #[test]
+    fn test_ai_function_name() {
+        assert_eq!(ai_function_name(&function_prompt(print_site_urls)).as_deref(), Some("print_site_urls"));
+        assert_eq!(ai_function_name(&[Message { role: "user".to_string(), content: "hello".to_string(), images: Vec::new() }]), None);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_failures_then_sticky_reply() {
+        let mock = MockProvider::new();
+        mock.fail("convert_user_input_to_goal", LlmError::Transport("connection reset".to_string()))
+            .respond("convert_user_input_to_goal", "build a website that tracks todo items");
+
+        let first = mock.send_messages(function_prompt(convert_user_input_to_goal), &GenerationParams::default()).await;
+        let second = mock.send_messages(function_prompt(convert_user_input_to_goal), &GenerationParams::default()).await.unwrap();
+        let third = mock.send_messages(function_prompt(convert_user_input_to_goal), &GenerationParams::default()).await.unwrap();
+
+        assert!(matches!(first, Err(LlmError::Transport(_))));
+        assert_eq!(second.content, "build a website that tracks todo items");
+        assert_eq!(third.content, second.content);
+        assert_eq!(mock.requests_for("convert_user_input_to_goal").len(), 3);
+        mock.assert_prompt_contains("convert_user_input_to_goal", "Build a todo app");
+    }
+
+    #[tokio::test]
+    async fn test_unscripted_function_fails() {
+        let mock = MockProvider::new();
+        mock.respond("convert_user_input_to_goal", "build a website");
+
+        let res = mock.send_messages(function_prompt(print_site_urls), &GenerationParams::default()).await;
+
+        assert!(matches!(res, Err(LlmError::MalformedResponse(_))));
+        assert_eq!(mock.requests().len(), 1);
+    }
+}
@@ -0,0 +1,12 @@
+pub mod anthropic_provider;
+pub mod cassette_provider;
+pub mod llama_provider;
+#[cfg(test)]
+pub mod mock_provider;
+pub mod model_router;
+pub mod openai_compatible_provider;
+pub mod openai_provider;
+pub mod provider_registry;
+pub mod provider_traits;
+pub mod provider_utils;
+pub mod sse;
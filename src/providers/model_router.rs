@@ -0,0 +1,113 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::models::general::{config::AcadiaConfig, llm_error::LlmError};
+
+use super::{provider_registry::ProviderRegistry, provider_traits::ProviderHandle};
+
+/// `routing` section of `acadia.json`, values are provider registry names.
+/// AI function routes win over agent routes, both win over the LLM picked at the start of the run.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RoutingConfig {
+    /// Keyed by agent position, e.g. "Project Manager"
+    pub agents: HashMap<String, String>,
+    /// Keyed by AI function name, e.g. "print_backend_webserver_code"
+    pub functions: HashMap<String, String>,
+    pub fallbacks: FallbackConfig
+}
+
+/// Providers tried in order once the routed provider keeps failing, keyed like the routes.
+/// Requests without an entry never fall back.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    pub agents: HashMap<String, Vec<String>>,
+    pub functions: HashMap<String, Vec<String>>
+}
+
+impl RoutingConfig {
+    /// Provider configured for this request, None when the agent's own provider should answer
+    pub fn route(&self, agent_position: &str, ai_function: &str) -> Option<&str> {
+        self.functions.get(ai_function)
+            .or_else(|| self.agents.get(agent_position))
+            .map(String::as_str)
+    }
+
+    /// Every provider name the table refers to along with what refers to it, e.g. "fallback of print_project_scope"
+    pub fn referenced_providers(&self) -> Vec<(String, String)> {
+        let routes = self.agents.iter().chain(self.functions.iter())
+            .map(|(key, name)| (name.clone(), format!("route of {}", key)));
+        let fallbacks = self.fallbacks.agents.iter().chain(self.fallbacks.functions.iter())
+            .flat_map(|(key, names)| names.iter().map(move |name| (name.clone(), format!("fallback of {}", key))));
+
+        let mut referenced: Vec<(String, String)> = routes.chain(fallbacks).collect();
+        referenced.sort();
+        referenced
+    }
+
+    /// Fallback chain for this request, AI function chains win over agent chains
+    pub fn fallbacks(&self, agent_position: &str, ai_function: &str) -> &[String] {
+        self.fallbacks.functions.get(ai_function)
+            .or_else(|| self.fallbacks.agents.get(agent_position))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+fn registered_provider(name: &str, agent_position: &str, ai_function: &str) -> Result<ProviderHandle, LlmError> {
+    ProviderRegistry::global()
+        .get(name)
+        .ok_or_else(|| LlmError::MissingConfig(format!("routing table points {} / {} to unknown provider {}", agent_position, ai_function, name)))
+}
+
+/// Provider that answers a request, `agent_provider` unless the routing table says otherwise
+pub fn route_provider(agent_provider: ProviderHandle, agent_position: &str, ai_function: &str) -> Result<ProviderHandle, LlmError> {
+    match AcadiaConfig::global().routing.route(agent_position, ai_function) {
+        Some(name) => registered_provider(name, agent_position, ai_function),
+        None => Ok(agent_provider)
+    }
+}
+
+/// The routed provider followed by its configured fallbacks, never empty
+pub fn route_chain(agent_provider: ProviderHandle, agent_position: &str, ai_function: &str) -> Result<Vec<ProviderHandle>, LlmError> {
+    let mut chain = vec![route_provider(agent_provider, agent_position, ai_function)?];
+
+    for name in AcadiaConfig::global().routing.fallbacks(agent_position, ai_function) {
+        if chain.iter().all(|provider| provider.name() != name) {
+            chain.push(registered_provider(name, agent_position, ai_function)?);
+        }
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_function_routes_win_over_agent_routes() {
+        let routing: RoutingConfig = serde_json::from_str(r#"{
+            "agents": { "Project Manager": "Llama3", "Backend Developer": "GPT-4o" },
+            "functions": { "print_backend_webserver_code": "Claude" }
+        }"#).unwrap();
+
+        assert_eq!(routing.route("Project Manager", "convert_user_input_to_goal"), Some("Llama3"));
+        assert_eq!(routing.route("Backend Developer", "print_backend_webserver_code"), Some("Claude"));
+        assert_eq!(routing.route("Solutions Architect", "print_project_scope"), None);
+    }
+
+    #[test]
+    fn test_fallbacks_are_opt_in() {
+        let routing: RoutingConfig = serde_json::from_str(r#"{
+            "fallbacks": {
+                "agents": { "Project Manager": ["Claude", "Llama3"] },
+                "functions": { "print_backend_webserver_code": ["Claude"] }
+            }
+        }"#).unwrap();
+
+        assert_eq!(routing.fallbacks("Project Manager", "convert_user_input_to_goal"), ["Claude", "Llama3"]);
+        assert_eq!(routing.fallbacks("Backend Developer", "print_backend_webserver_code"), ["Claude"]);
+        assert!(routing.fallbacks("Solutions Architect", "print_project_scope").is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+use dotenv::dotenv;
+use reqwest::{header::HeaderMap, Method, RequestBuilder, Response};
+use std::env;
+
+use crate::{models::general::{generation::GenerationParams, llm::{LLMCompletion, LLMRequestBody, Message, OpenAIResponse, TokenUsage}, llm_error::LlmError, tools::{ToolCompletion, ToolDefinition, ToolTurn}}, utils::http_client::shared_client};
+
+use super::{openai_provider::{first_choice_content, openai_tool_body, openai_tool_completion, parse_openai_event}, provider_traits::{LLMStream, LlmProvider}, provider_utils::{header_value, ping_endpoint, read_json}, sse::sse_delta_stream};
+
+const DEFAULT_LOCAL_MODEL: &str = "llama3";
+
+/// Any server exposing an OpenAI compatible `/v1/chat/completions` route
+/// (vLLM, llama.cpp server, LM Studio, Ollama). The API key is optional and
+/// no `OpenAI-Organization` header is sent.
+#[derive(Debug)]
+pub struct OpenAICompatibleProvider {
+    name: String,
+    base_url: String,
+    model: String,
+    api_key: Option<String>
+}
+
+impl OpenAICompatibleProvider {
+    pub fn new(name: &str, base_url: &str, model: &str, api_key: Option<String>) -> Self {
+        OpenAICompatibleProvider {
+            name: name.to_string(),
+            base_url: base_url.to_string(),
+            model: model.to_string(),
+            api_key
+        }
+    }
+
+    /// Reads `LLM_BASE_URL`, `LLM_LOCAL_MODEL` and `LLM_API_KEY` from the .env file.
+    /// Returns None when no base url is configured.
+    pub fn from_env(name: &str) -> Option<Self> {
+        dotenv().ok();
+
+        let base_url = env::var("LLM_BASE_URL").ok()?;
+        let model = env::var("LLM_LOCAL_MODEL").unwrap_or_else(|_| DEFAULT_LOCAL_MODEL.to_string());
+        let api_key = env::var("LLM_API_KEY").ok().filter(|key| !key.is_empty());
+
+        Some(OpenAICompatibleProvider::new(name, &base_url, &model, api_key))
+    }
+
+    /// Accepts base urls with or without the trailing `/v1`
+    fn chat_completions_url(&self) -> String {
+        let base_url = self.base_url.trim_end_matches('/');
+        if base_url.ends_with("/v1") {
+            format!("{}/chat/completions", base_url)
+        } else {
+            format!("{}/v1/chat/completions", base_url)
+        }
+    }
+
+    /// Request to the chat completions url, with the auth header when a key is configured
+    fn request(&self, method: Method) -> Result<RequestBuilder, LlmError> {
+        let mut header_map = HeaderMap::new();
+        if let Some(key) = &self.api_key {
+            header_map.insert("authorization", header_value("LLM_API_KEY", &format!("Bearer {}", key))?);
+        }
+
+        Ok(shared_client()?.request(method, self.chat_completions_url()).headers(header_map))
+    }
+
+    async fn post_chat(&self, messages: Vec<Message>, params: &GenerationParams, stream: bool) -> Result<Response, LlmError> {
+        let request = self.request(Method::POST)?;
+
+        let mut llm_req_body = LLMRequestBody::new(messages, self.model.clone()).with_params(params);
+        llm_req_body.stream = stream;
+        let response = request
+            .json(&llm_req_body)
+            .send()
+            .await?;
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAICompatibleProvider {
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    fn check_config(&self) -> Result<(), LlmError> {
+        self.request(Method::POST).map(|_| ())
+    }
+
+    async fn ping(&self) -> Result<(), LlmError> {
+        ping_endpoint(self.request(Method::GET)?).await
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let response = self.post_chat(messages, params, false).await?;
+        let mut res: OpenAIResponse = read_json(response).await?;
+        let usage = res.usage.take().map(TokenUsage::from);
+
+        Ok(LLMCompletion {
+            content: first_choice_content(res)?,
+            provider: self.name.clone(),
+            model: self.model.clone(),
+            usage
+        })
+    }
+
+    async fn stream_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMStream, LlmError> {
+        let response = self.post_chat(messages, params, true).await?;
+        sse_delta_stream(response, parse_openai_event).await
+    }
+
+    async fn send_with_tools(&self, conversation: Vec<ToolTurn>, tools: &[ToolDefinition], params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+        let response = self.request(Method::POST)?
+            .json(&openai_tool_body(&self.model, &conversation, tools, params)?)
+            .send()
+            .await?;
+
+        openai_tool_completion(read_json(response).await?, &self.name, &self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+    use wiremock::{matchers::{body_partial_json, header, method, path}, Mock, MockServer, ResponseTemplate};
+
+    use futures_util::StreamExt;
+
+    use crate::models::general::llm::LLMStreamEvent;
+
+    use super::*;
+
+    fn chat_response(content: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": content } }]
+        }))
+    }
+
+    #[test]
+    fn test_chat_completions_url() {
+        let with_version = OpenAICompatibleProvider::new("Local", "http://localhost:11434/v1/", "llama3", None);
+        let without_version = OpenAICompatibleProvider::new("Local", "http://localhost:8000", "llama3", None);
+
+        assert_eq!(with_version.chat_completions_url(), "http://localhost:11434/v1/chat/completions");
+        assert_eq!(without_version.chat_completions_url(), "http://localhost:8000/v1/chat/completions");
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_request_without_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({ "model": "llama3" })))
+            .respond_with(chat_response("build a website that tracks todo items"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = OpenAICompatibleProvider::new("Local", &format!("{}/v1", server.uri()), "llama3", None);
+        let messages = vec![Message { role: "user".to_string(), content: "Build a todo app".to_string(), images: Vec::new() }];
+
+        let completion = provider.send_messages(messages, &GenerationParams::default()).await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = requests[0].body_json().unwrap();
+
+        assert_eq!(completion.content, "build a website that tracks todo items");
+        assert_eq!(completion.provider, "Local");
+        assert!(requests[0].headers.get("authorization").is_none());
+        assert!(requests[0].headers.get("OpenAI-Organization").is_none());
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("response_format").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_request_with_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(header("authorization", "Bearer local-key"))
+            .and(body_partial_json(json!({ "temperature": 0.0, "max_tokens": 256, "response_format": { "type": "json_object" } })))
+            .respond_with(chat_response("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = OpenAICompatibleProvider::new("Local", &server.uri(), "qwen2", Some("local-key".to_string()));
+        let messages = vec![Message { role: "user".to_string(), content: "ping".to_string(), images: Vec::new() }];
+        let params = GenerationParams { temperature: Some(0.0), max_tokens: Some(256), json_mode: Some(true), ..GenerationParams::default() };
+
+        let completion = provider.send_messages(messages, &params).await.unwrap();
+
+        assert_eq!(completion.content, "ok");
+        assert_eq!(completion.model, "qwen2");
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_stream() {
+        let server = MockServer::start().await;
+        let events = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"fn \"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"main() {}\"}}]}\n\n",
+            "data: [DONE]\n\n"
+        );
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(events, "text/event-stream"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = OpenAICompatibleProvider::new("Local", &server.uri(), "llama3", None);
+        let messages = vec![Message { role: "user".to_string(), content: "Write a main function".to_string(), images: Vec::new() }];
+
+        let events: Vec<LLMStreamEvent> = provider.stream_messages(messages, &GenerationParams::default())
+            .await
+            .unwrap()
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(events, vec![
+            LLMStreamEvent::Delta("fn ".to_string()),
+            LLMStreamEvent::Delta("main() {}".to_string())
+        ]);
+    }
+}
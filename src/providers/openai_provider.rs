@@ -0,0 +1,285 @@
+use async_trait::async_trait;
+use reqwest::{header::HeaderMap, Method, RequestBuilder, Response};
+use serde_json::{json, Value};
+
+use crate::{models::general::{generation::GenerationParams, llm::{openai_message_json, LLMCompletion, LLMRequestBody, LLMStreamEvent, Message, OpenAIResponse, OpenAIStreamChunk, OpenAIToolResponse, StreamOptions, TokenUsage}, llm_error::LlmError, tools::{ToolCall, ToolCompletion, ToolDefinition, ToolTurn}}, utils::http_client::shared_client};
+
+use super::{provider_registry::GPT4O, provider_traits::{LLMStream, LlmProvider}, provider_utils::{header_value, ping_endpoint, read_json, required_env, required_envs}, sse::sse_delta_stream};
+
+const DEFAULT_OPENAI_MODEL: &str = "gpt-4o";
+
+/// OpenAI chat completions API, configured through the .env file
+#[derive(Debug, Default)]
+pub struct OpenAIProvider;
+
+impl OpenAIProvider {
+    pub fn new() -> Self {
+        OpenAIProvider
+    }
+
+    /// Request to the chat completions url with the auth headers, along with the model it is for
+    fn request(&self, method: Method) -> Result<(RequestBuilder, String), LlmError> {
+        let url: String = required_env("OPEN_AI_URL")?;
+        self.request_to(method, &url)
+    }
+
+    /// Request to `url` with the auth headers, along with the model it is for
+    fn request_to(&self, method: Method, url: &str) -> Result<(RequestBuilder, String), LlmError> {
+
+        // OPENAI creds
+        let key: String = required_env("OPEN_AI_KEY")?;
+        let org: String = required_env("OPEN_AI_ORG")?;
+        let model: String = required_env("LLM_MODEL")?;
+
+        Ok((shared_client()?.request(method, url).headers(auth_headers(&key, &org)?), model))
+    }
+
+    /// Sends the chat request and hands back the raw response along with the model used
+    async fn post_chat(&self, messages: Vec<Message>, params: &GenerationParams, stream: bool) -> Result<(Response, String), LlmError> {
+        let (request, model) = self.request(Method::POST)?;
+
+        let mut llm_req_body = LLMRequestBody::new(messages, model.clone()).with_params(params);
+        llm_req_body.stream = stream;
+        if stream {
+            llm_req_body.stream_options = Some(StreamOptions { include_usage: true });
+        }
+        let response = request
+            .json(&llm_req_body)
+            .send()
+            .await?;
+
+        Ok((response, model))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAIProvider {
+
+    fn name(&self) -> &str {
+        GPT4O
+    }
+
+    fn model(&self) -> String {
+        required_env("LLM_MODEL").unwrap_or_else(|_| DEFAULT_OPENAI_MODEL.to_string())
+    }
+
+    fn check_config(&self) -> Result<(), LlmError> {
+        required_envs(&["OPEN_AI_URL", "OPEN_AI_KEY", "OPEN_AI_ORG", "LLM_MODEL"])?;
+        self.request(Method::POST).map(|_| ())
+    }
+
+    /// Lists the models, which unlike the chat completions url answers a GET and rejects a bad key or organization
+    async fn ping(&self) -> Result<(), LlmError> {
+        let url = models_url(&required_env("OPEN_AI_URL")?);
+        ping_endpoint(self.request_to(Method::GET, &url)?.0).await
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+        let (response, model) = self.post_chat(messages, params, false).await?;
+        let mut res: OpenAIResponse = read_json(response).await?;
+        let usage = res.usage.take().map(TokenUsage::from);
+
+        Ok(LLMCompletion {
+            content: first_choice_content(res)?,
+            provider: self.name().to_string(),
+            model,
+            usage
+        })
+    }
+
+    async fn stream_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMStream, LlmError> {
+        let (response, _) = self.post_chat(messages, params, true).await?;
+        sse_delta_stream(response, parse_openai_event).await
+    }
+
+    async fn send_with_tools(&self, conversation: Vec<ToolTurn>, tools: &[ToolDefinition], params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+        let (request, model) = self.request(Method::POST)?;
+        let response = request
+            .json(&openai_tool_body(&model, &conversation, tools, params)?)
+            .send()
+            .await?;
+
+        openai_tool_completion(read_json(response).await?, self.name(), &model)
+    }
+}
+
+/// Chat completions body with `tools`, tool calls and results use the `tool_calls` and `tool` role messages
+pub fn openai_tool_body(model: &str, conversation: &[ToolTurn], tools: &[ToolDefinition], params: &GenerationParams) -> Result<Value, LlmError> {
+    let messages: Vec<Value> = conversation.iter().map(|turn| match turn {
+        ToolTurn::Message(message) => openai_message_json(message),
+        ToolTurn::ToolCalls { content, calls } => json!({
+            "role": "assistant",
+            "content": if content.is_empty() { Value::Null } else { Value::from(content.as_str()) },
+            "tool_calls": calls.iter().map(|call| json!({
+                "id": call.id,
+                "type": "function",
+                "function": { "name": call.name, "arguments": call.arguments.to_string() }
+            })).collect::<Vec<Value>>()
+        }),
+        ToolTurn::ToolResult { call_id, content, .. } => json!({ "role": "tool", "tool_call_id": call_id, "content": content })
+    }).collect();
+
+    let mut body = serde_json::to_value(LLMRequestBody::new(Vec::new(), model.to_string()).with_params(params))
+        .map_err(|e| LlmError::MalformedResponse(format!("could not build tool request: {}", e)))?;
+    body["messages"] = Value::Array(messages);
+    body["tools"] = tools.iter().map(|tool| json!({
+        "type": "function",
+        "function": { "name": tool.name, "description": tool.description, "parameters": tool.parameters }
+    })).collect();
+    Ok(body)
+}
+
+/// Text and tool calls of the first choice, shared by every OpenAI shaped provider
+pub fn openai_tool_completion(mut res: OpenAIToolResponse, provider: &str, model: &str) -> Result<ToolCompletion, LlmError> {
+    let usage = res.usage.take().map(TokenUsage::from);
+    let message = res.choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| LlmError::MalformedResponse("response contained no choices".to_string()))?
+        .message;
+
+    let tool_calls = message.tool_calls
+        .into_iter()
+        .map(|call| {
+            let arguments = serde_json::from_str(&call.function.arguments).map_err(|e| LlmError::MalformedResponse(
+                format!("arguments of tool call {} are not JSON ({}): {}", call.function.name, e, call.function.arguments)
+            ))?;
+            Ok(ToolCall { id: call.id, name: call.function.name, arguments })
+        })
+        .collect::<Result<Vec<ToolCall>, LlmError>>()?;
+
+    Ok(ToolCompletion {
+        completion: LLMCompletion {
+            content: message.content.unwrap_or_default(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            usage
+        },
+        tool_calls
+    })
+}
+
+/// Text of the first choice, shared by every OpenAI shaped provider
+pub fn first_choice_content(res: OpenAIResponse) -> Result<String, LlmError> {
+    res.choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.message.content)
+        .ok_or_else(|| LlmError::MalformedResponse("response contained no choices".to_string()))
+}
+
+/// Text or usage of one streamed chat completion chunk
+pub fn parse_openai_event(data: &str) -> Option<LLMStreamEvent> {
+    let chunk: OpenAIStreamChunk = serde_json::from_str(data).ok()?;
+    if let Some(usage) = chunk.usage {
+        return Some(LLMStreamEvent::Usage(usage.into()));
+    }
+    chunk.choices.into_iter().next()?.delta.content.map(LLMStreamEvent::Delta)
+}
+
+fn auth_headers(key: &str, org: &str) -> Result<HeaderMap, LlmError> {
+    let mut header_map = HeaderMap::new();
+    header_map.insert("authorization", header_value("OPEN_AI_KEY", &format!("Bearer {}", key))?);
+    header_map.insert("OpenAI-Organization", header_value("OPEN_AI_ORG", org)?);
+    Ok(header_map)
+}
+
+/// Models url beside the configured chat completions url, OPEN_AI_URL may also be the bare API base
+fn models_url(chat_url: &str) -> String {
+    let url = chat_url.trim_end_matches('/');
+    format!("{}/models", url.strip_suffix("/chat/completions").unwrap_or(url))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use wiremock::{matchers::{header, method, path}, Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    #[test]
+    fn test_models_url() {
+        assert_eq!(models_url("https://api.openai.com/v1/chat/completions"), "https://api.openai.com/v1/models");
+        assert_eq!(models_url("https://api.openai.com/v1/"), "https://api.openai.com/v1/models");
+    }
+
+    #[tokio::test]
+    async fn test_ping_checks_the_credentials() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .and(header("authorization", "Bearer sk-test"))
+            .and(header("OpenAI-Organization", "org-test"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(401).set_body_string("invalid api key")).mount(&server).await;
+        let url = models_url(&format!("{}/v1/chat/completions", server.uri()));
+        let client = reqwest::Client::new();
+
+        assert!(ping_endpoint(client.get(&url).headers(auth_headers("sk-test", "org-test").unwrap())).await.is_ok());
+        match ping_endpoint(client.get(&url).headers(auth_headers("sk-wrong", "org-test").unwrap())).await {
+            Err(LlmError::HttpStatus { status, .. }) => assert_eq!(status, 401),
+            other => panic!("Expected rejected credentials, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_parse_openai_event() {
+        let chunk = r#"{"id":"chatcmpl-1","choices":[{"index":0,"delta":{"content":"fn main"}}]}"#;
+        let role_only = r#"{"id":"chatcmpl-1","choices":[{"index":0,"delta":{"role":"assistant"}}]}"#;
+        let usage = r#"{"id":"chatcmpl-1","choices":[],"usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42}}"#;
+
+        assert_eq!(parse_openai_event(chunk), Some(LLMStreamEvent::Delta("fn main".to_string())));
+        assert_eq!(parse_openai_event(role_only), None);
+        assert_eq!(parse_openai_event(usage), Some(LLMStreamEvent::Usage(TokenUsage { prompt_tokens: 12, completion_tokens: 30 })));
+    }
+
+    #[test]
+    fn test_openai_tool_body() {
+        let conversation = vec![
+            ToolTurn::Message(Message { role: "user".to_string(), content: "Is https://example.com up?".to_string(), images: Vec::new() }),
+            ToolTurn::ToolCalls {
+                content: String::new(),
+                calls: vec![ToolCall { id: "call_1".to_string(), name: "check_url".to_string(), arguments: json!({ "url": "https://example.com" }) }]
+            },
+            ToolTurn::ToolResult { call_id: "call_1".to_string(), name: "check_url".to_string(), content: "200".to_string() }
+        ];
+        let tools = vec![ToolDefinition { name: "check_url".to_string(), description: "GET a url".to_string(), parameters: json!({ "type": "object" }) }];
+
+        let body = openai_tool_body("gpt-4o", &conversation, &tools, &GenerationParams { temperature: Some(0.0), ..GenerationParams::default() }).unwrap();
+
+        assert_eq!(body["temperature"], json!(0.0));
+        assert_eq!(body["tools"][0]["function"]["name"], "check_url");
+        assert_eq!(body["messages"][1]["content"], Value::Null);
+        assert_eq!(body["messages"][1]["tool_calls"][0]["function"]["arguments"], r#"{"url":"https://example.com"}"#);
+        assert_eq!(body["messages"][2], json!({ "role": "tool", "tool_call_id": "call_1", "content": "200" }));
+    }
+
+    #[test]
+    fn test_openai_tool_completion() {
+        let res: OpenAIToolResponse = serde_json::from_str(r#"{
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": null, "tool_calls": [
+                { "id": "call_1", "type": "function", "function": { "name": "check_url", "arguments": "{\"url\": \"https://example.com\"}" } }
+            ] } }],
+            "usage": { "prompt_tokens": 50, "completion_tokens": 12 }
+        }"#).unwrap();
+
+        let completion = openai_tool_completion(res, GPT4O, "gpt-4o").unwrap();
+
+        assert_eq!(completion.completion.content, "");
+        assert_eq!(completion.tool_calls, vec![ToolCall { id: "call_1".to_string(), name: "check_url".to_string(), arguments: json!({ "url": "https://example.com" }) }]);
+        assert_eq!(completion.completion.usage, Some(TokenUsage { prompt_tokens: 50, completion_tokens: 12 }));
+    }
+
+    #[test]
+    fn test_first_choice_content_without_choices() {
+        let res = OpenAIResponse { choices: vec![], usage: None };
+
+        assert!(matches!(first_choice_content(res), Err(LlmError::MalformedResponse(_))));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, sync::{Arc, OnceLock}};
+
+use super::{anthropic_provider::AnthropicProvider, cassette_provider::{CassetteMode, CassetteProvider}, llama_provider::LlamaProvider, openai_compatible_provider::OpenAICompatibleProvider, openai_provider::OpenAIProvider, provider_traits::ProviderHandle};
+
+/// Registry names, these are also the choices offered to the user in the CLI
+pub const GPT4O: &str = "GPT-4o";
+pub const CLAUDE: &str = "Claude";
+pub const LLAMA3: &str = "Llama3";
+
+static REGISTRY: OnceLock<ProviderRegistry> = OnceLock::new();
+
+/// Looks up LLM providers by name so agents never depend on a concrete provider
+#[derive(Debug, Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, ProviderHandle>
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        ProviderRegistry {
+            providers: HashMap::new()
+        }
+    }
+
+    /// Registry with every provider that ships with Acadia.
+    /// With `ACADIA_CASSETTE=record|replay` every provider is wrapped in a `CassetteProvider`.
+    pub fn with_defaults() -> Self {
+        Self::with_cassette_mode(CassetteMode::from_env())
+    }
+
+    /// Default providers for tests, replaying the cassettes committed under `cassettes/`
+    /// unless `ACADIA_CASSETTE=record` asks to record them again
+    #[cfg(test)]
+    pub fn with_cassettes() -> Self {
+        Self::with_cassette_mode(Some(CassetteMode::from_env().unwrap_or(CassetteMode::Replay)))
+    }
+
+    fn with_cassette_mode(cassette_mode: Option<CassetteMode>) -> Self {
+        let mut registry = ProviderRegistry::new();
+        registry.register(GPT4O, Arc::new(OpenAIProvider::new()));
+        registry.register(CLAUDE, Arc::new(AnthropicProvider::new()));
+
+        // A local server speaking the OpenAI protocol takes precedence over the legacy LLM_URL server
+        match OpenAICompatibleProvider::from_env(LLAMA3) {
+            Some(local_provider) => registry.register(LLAMA3, Arc::new(local_provider)),
+            None => registry.register(LLAMA3, Arc::new(LlamaProvider::new()))
+        }
+
+        for provider in registry.providers.values_mut() {
+            *provider = CassetteProvider::wrap(provider.clone(), cassette_mode);
+        }
+        registry
+    }
+
+    /// Default registry shared by the whole run, used to resolve routed requests
+    pub fn global() -> &'static ProviderRegistry {
+        REGISTRY.get_or_init(ProviderRegistry::with_defaults)
+    }
+
+    /// Provider the user picked for the run, `agent_default` when nothing or an unknown name was picked
+    pub fn run_provider(&self, llm_choice: Option<&str>, agent_default: &str) -> ProviderHandle {
+        llm_choice
+            .and_then(|name| self.get(name))
+            .or_else(|| self.get(agent_default))
+            .unwrap_or_else(|| panic!("{} provider is not registered", agent_default))
+    }
+
+    /// Adds a provider, replacing any provider already registered under the same name
+    pub fn register(&mut self, name: &str, provider: ProviderHandle) {
+        self.providers.insert(name.to_string(), provider);
+    }
+
+    pub fn get(&self, name: &str) -> Option<ProviderHandle> {
+        self.providers.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_default_providers_registered() {
+        let registry = ProviderRegistry::with_defaults();
+
+        assert_eq!(registry.get(GPT4O).unwrap().name(), GPT4O);
+        assert_eq!(registry.get(CLAUDE).unwrap().name(), CLAUDE);
+        assert_eq!(registry.get(LLAMA3).unwrap().name(), LLAMA3);
+        assert!(registry.get("Unknown").is_none());
+    }
+
+    #[test]
+    fn test_run_provider_prefers_user_choice() {
+        let registry = ProviderRegistry::with_defaults();
+
+        assert_eq!(registry.run_provider(Some(CLAUDE), LLAMA3).name(), CLAUDE);
+        assert_eq!(registry.run_provider(None, LLAMA3).name(), LLAMA3);
+        assert_eq!(registry.run_provider(Some("Unknown"), GPT4O).name(), GPT4O);
+    }
+
+    #[test]
+    fn test_register_replaces_provider() {
+        let mut registry = ProviderRegistry::new();
+        registry.register("Local", Arc::new(LlamaProvider::new()));
+        registry.register("Local", Arc::new(OpenAIProvider::new()));
+
+        assert_eq!(registry.providers.len(), 1);
+        assert_eq!(registry.get("Local").unwrap().name(), GPT4O);
+    }
+}
@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use futures_util::{stream, Stream};
+use std::{fmt::Debug, pin::Pin, sync::Arc};
+
+use crate::models::general::{config::AcadiaConfig, generation::GenerationParams, llm::{LLMCompletion, LLMStreamEvent, Message}, llm_error::LlmError, tools::{ToolCompletion, ToolDefinition, ToolTurn}};
+
+/// Shared handle to a provider, cheap to clone into every agent
+pub type ProviderHandle = Arc<dyn LlmProvider>;
+
+/// Text deltas of an answer in the order they were generated, plus token usage when the provider reports it.
+/// Dropping the stream aborts the request.
+pub type LLMStream = Pin<Box<dyn Stream<Item = Result<LLMStreamEvent, LlmError>> + Send>>;
+
+/// Anything that can take a list of chat messages and answer with text
+#[async_trait]
+pub trait LlmProvider: Debug + Send + Sync {
+
+    /// Name the provider is registered under
+    fn name(&self) -> &str;
+
+    /// Model the provider sends requests to, used to price and label its answers
+    fn model(&self) -> String;
+
+    /// Checks that the credentials and urls the provider needs are configured, without sending anything
+    fn check_config(&self) -> Result<(), LlmError> {
+        Ok(())
+    }
+
+    /// Reaches the provider's endpoint to confirm it is up and accepts the credentials, nothing is generated
+    async fn ping(&self) -> Result<(), LlmError> {
+        Ok(())
+    }
+
+    /// Whether messages may carry images, taken from the capability table of the model
+    fn supports_vision(&self) -> bool {
+        AcadiaConfig::global().models.capability(&self.model()).is_some_and(|capability| capability.vision)
+    }
+
+    /// Parameters the provider does not support are ignored with a warning
+    async fn send_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMCompletion, LlmError>;
+
+    /// Providers without streaming support answer with the whole completion as a single delta
+    async fn stream_messages(&self, messages: Vec<Message>, params: &GenerationParams) -> Result<LLMStream, LlmError> {
+        let completion = self.send_messages(messages, params).await?;
+
+        let mut events = vec![Ok(LLMStreamEvent::Delta(completion.content))];
+        if let Some(usage) = completion.usage {
+            events.push(Ok(LLMStreamEvent::Usage(usage)));
+        }
+        Ok(Box::pin(stream::iter(events)))
+    }
+
+    /// Whether `send_with_tools` is implemented, agents fall back to plain requests when it is not
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    /// Sends the conversation along with the tools the model may call.
+    /// Providers without native tool calling answer with an error.
+    async fn send_with_tools(&self, _conversation: Vec<ToolTurn>, _tools: &[ToolDefinition], _params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+        Err(LlmError::ToolCalling(format!("{} does not support tool calling", self.name())))
+    }
+}
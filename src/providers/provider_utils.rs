@@ -0,0 +1,157 @@
+use crossterm::style::Color;
+use dotenv::dotenv;
+use reqwest::{header::{HeaderValue, RETRY_AFTER}, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::{collections::HashSet, env, sync::{Mutex, OnceLock}, time::Duration};
+
+use crate::{models::general::{generation::GenerationParams, llm_error::LlmError}, utils::command_line::LogMessage};
+
+/// Provider and parameter pairs already warned about, each is only reported once per run
+static UNSUPPORTED_WARNINGS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// Reads a variable the provider cannot work without
+pub fn required_env(name: &str) -> Result<String, LlmError> {
+    dotenv().ok();
+    env::var(name).map_err(|_| LlmError::MissingConfig(format!("{} is not set in the .env file", name)))
+}
+
+/// Checks several variables at once so a single report lists everything that is missing
+pub fn required_envs(names: &[&str]) -> Result<(), LlmError> {
+    dotenv().ok();
+    let missing: Vec<&str> = names.iter().copied().filter(|name| env::var(name).is_err()).collect();
+    match missing.as_slice() {
+        [] => Ok(()),
+        [name] => Err(LlmError::MissingConfig(format!("{} is not set in the .env file", name))),
+        names => Err(LlmError::MissingConfig(format!("{} are not set in the .env file", names.join(", "))))
+    }
+}
+
+pub fn header_value(name: &str, value: &str) -> Result<HeaderValue, LlmError> {
+    HeaderValue::from_str(value).map_err(|e| LlmError::MissingConfig(format!("{} is not a valid header value: {}", name, e)))
+}
+
+/// Warns about generation parameters the provider does not support, they are left out of the request
+pub fn warn_unsupported_params(provider: &str, params: &GenerationParams, supported: &[&str]) {
+    let mut warned = UNSUPPORTED_WARNINGS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+
+    for param in params.set_params().into_iter().filter(|param| !supported.contains(param)) {
+        if warned.insert(format!("{}:{}", provider, param)) {
+            LogMessage::Error.print_message(&format!("{} does not support the `{}` generation parameter, ignoring it", provider, param), Color::Yellow);
+        }
+    }
+}
+
+/// Sends a GET to the endpoint without generating anything. Any answer means it is reachable,
+/// only 401 and 403 count as failures since they mean the credentials were rejected.
+pub async fn ping_endpoint(request: RequestBuilder) -> Result<(), LlmError> {
+    let response = request.send().await?;
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(LlmError::HttpStatus {
+            status: response.status().as_u16(),
+            body: response.text().await.unwrap_or_default()
+        }),
+        _ => Ok(())
+    }
+}
+
+/// Turns non success responses into errors, keeping the body so the cause is visible
+pub async fn check_response(response: Response) -> Result<Response, LlmError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response.headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+    let body = response.text().await.unwrap_or_default();
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        Err(LlmError::RateLimited { retry_after, body })
+    } else {
+        Err(LlmError::HttpStatus { status: status.as_u16(), body })
+    }
+}
+
+/// Parses a successful response body, reporting the raw body when it does not match `T`
+pub async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, LlmError> {
+    let body = check_response(response).await?.text().await?;
+    serde_json::from_str(&body).map_err(|e| LlmError::MalformedResponse(format!("{} in {}", e, body)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use wiremock::{matchers::{method, path}, Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    async fn respond_with(template: ResponseTemplate) -> Response {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(template).mount(&server).await;
+        reqwest::get(server.uri()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ping_endpoint() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).and(path("/v1/chat/completions")).respond_with(ResponseTemplate::new(405)).mount(&server).await;
+        Mock::given(method("GET")).and(path("/v1/messages")).respond_with(ResponseTemplate::new(401).set_body_string("invalid x-api-key")).mount(&server).await;
+        let client = reqwest::Client::new();
+
+        assert!(ping_endpoint(client.get(format!("{}/v1/chat/completions", server.uri()))).await.is_ok());
+        match ping_endpoint(client.get(format!("{}/v1/messages", server.uri()))).await {
+            Err(LlmError::HttpStatus { status, body }) => assert_eq!((status, body.as_str()), (401, "invalid x-api-key")),
+            other => panic!("Expected rejected credentials, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_required_envs_lists_every_missing_variable() {
+        match required_envs(&["ACADIA_TEST_UNSET_A", "ACADIA_TEST_UNSET_B"]) {
+            Err(LlmError::MissingConfig(msg)) => assert_eq!(msg, "ACADIA_TEST_UNSET_A, ACADIA_TEST_UNSET_B are not set in the .env file"),
+            other => panic!("Expected missing config, got {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_response_rate_limited() {
+        let response = respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7")).await;
+
+        match check_response(response).await {
+            Err(LlmError::RateLimited { retry_after, .. }) => assert_eq!(retry_after, Some(Duration::from_secs(7))),
+            other => panic!("Expected rate limited error, got {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_response_keeps_error_body() {
+        let response = respond_with(ResponseTemplate::new(401).set_body_string("invalid api key")).await;
+
+        match check_response(response).await {
+            Err(LlmError::HttpStatus { status, body }) => {
+                assert_eq!(status, 401);
+                assert_eq!(body, "invalid api key");
+            },
+            other => panic!("Expected http status error, got {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_json_malformed_response() {
+        let response = respond_with(ResponseTemplate::new(200).set_body_string("<html>gateway</html>")).await;
+
+        let res = read_json::<serde_json::Value>(response).await;
+
+        assert!(matches!(res, Err(LlmError::MalformedResponse(body)) if body.contains("<html>gateway</html>")));
+    }
+
+    #[test]
+    fn test_required_env_missing() {
+        let res = required_env("ACADIA_TEST_VARIABLE_THAT_IS_NEVER_SET");
+
+        assert!(matches!(res, Err(LlmError::MissingConfig(_))));
+    }
+}
@@ -0,0 +1,95 @@
+use futures_util::stream;
+use reqwest::Response;
+use std::collections::VecDeque;
+
+use crate::models::general::{llm::LLMStreamEvent, llm_error::LlmError};
+
+use super::{provider_traits::LLMStream, provider_utils::check_response};
+
+/// Splits a server-sent events byte stream into the payload of each `data:` line.
+/// Bytes are buffered until a full line arrives, so chunks may end anywhere.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>
+}
+
+impl SseDecoder {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut payloads = Vec::new();
+        while let Some(line_end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=line_end).collect();
+            let line = String::from_utf8_lossy(&line);
+
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                payloads.push(data.trim_start().to_string());
+            }
+        }
+        payloads
+    }
+}
+
+/// Turns a streaming HTTP response into a stream of events, failing upfront on error statuses.
+/// `parse_event` pulls the text or usage out of one event payload, anything else is skipped.
+pub async fn sse_delta_stream(response: Response, parse_event: fn(&str) -> Option<LLMStreamEvent>) -> Result<LLMStream, LlmError> {
+    let response = check_response(response).await?;
+    let state = (response, SseDecoder::default(), VecDeque::<LLMStreamEvent>::new(), false);
+
+    Ok(Box::pin(stream::unfold(state, move |(mut response, mut decoder, mut pending, mut done)| async move {
+        loop {
+            if let Some(event) = pending.pop_front() {
+                return Some((Ok(event), (response, decoder, pending, done)));
+            }
+            if done {
+                return None;
+            }
+
+            match response.chunk().await {
+                Ok(Some(bytes)) => {
+                    for data in decoder.push(&bytes) {
+                        if data == "[DONE]" {
+                            done = true;
+                            break;
+                        }
+                        match parse_event(&data) {
+                            Some(LLMStreamEvent::Delta(delta)) if delta.is_empty() => {},
+                            Some(event) => pending.push_back(event),
+                            None => {}
+                        }
+                    }
+                },
+                Ok(None) => done = true,
+                Err(e) => {
+                    return Some((Err(LlmError::from(e)), (response, decoder, pending, true)));
+                }
+            }
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sse_decoder_handles_split_chunks() {
+        let mut decoder = SseDecoder::default();
+
+        let first = decoder.push(b"event: message\ndata: {\"a\":");
+        let second = decoder.push(b" 1}\n\ndata: [DONE]\n\n");
+
+        assert!(first.is_empty());
+        assert_eq!(second, vec!["{\"a\": 1}".to_string(), "[DONE]".to_string()]);
+    }
+
+    #[test]
+    fn test_sse_decoder_handles_crlf() {
+        let mut decoder = SseDecoder::default();
+
+        let payloads = decoder.push(b"data: hello\r\n\r\n: keep-alive\r\n");
+
+        assert_eq!(payloads, vec!["hello".to_string()]);
+    }
+}
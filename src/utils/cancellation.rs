@@ -0,0 +1,82 @@
+use std::future::Future;
+
+use crossterm::style::Color;
+use tokio_util::sync::CancellationToken;
+
+use crate::{models::general::llm_error::LlmError, utils::command_line::LogMessage};
+
+/// Exit code of a process stopped by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Awaits `request` unless the run is cancelled first. The request future is dropped on cancellation,
+/// which closes its connection so the provider stops generating.
+pub async fn cancellable<T>(cancel: &CancellationToken, request: impl Future<Output = Result<T, LlmError>>) -> Result<T, LlmError> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(LlmError::Cancelled),
+        res = request => res
+    }
+}
+
+/// Safe point of an agent, work already saved stays and nothing new is started once the run is cancelled
+pub fn check_cancelled(cancel: &CancellationToken) -> Result<(), LlmError> {
+    if cancel.is_cancelled() {
+        return Err(LlmError::Cancelled);
+    }
+    Ok(())
+}
+
+/// Cancels the run on the first Ctrl-C so agents can stop at a safe point, a second Ctrl-C exits right away
+pub fn cancel_on_ctrl_c(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        println!();
+        LogMessage::Error.print_message(
+            "Cancelling the run, waiting for the agents to reach a safe point. Press Ctrl-C again to quit right away",
+            Color::Yellow
+        );
+        cancel.cancel();
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancellable_aborts_pending_request() {
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            trigger.cancel();
+        });
+
+        let res: Result<(), LlmError> = cancellable(&cancel, async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        }).await;
+
+        assert!(matches!(res, Err(LlmError::Cancelled)));
+        assert!(matches!(check_cancelled(&cancel), Err(LlmError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_cancellable_passes_through_answer() {
+        let cancel = CancellationToken::new();
+
+        let res = cancellable(&cancel, async { Ok::<_, LlmError>("fn main() {}") }).await;
+
+        assert_eq!(res.unwrap(), "fn main() {}");
+        assert!(check_cancelled(&cancel).is_ok());
+    }
+}
@@ -0,0 +1,341 @@
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use std::{collections::HashMap, env, fs, io, marker::PhantomData, path::{Path, PathBuf}, process, sync::Arc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{agents::base::agent_traits::AgentState, models::general::{llm::Message, llm_error::LlmError}, providers::provider_traits::ProviderHandle, utils::{helper::cargo_build, json_repair::decode_llm_json, llm_requests::send_agent_messages}};
+
+/// `best_of` section of `acadia.json`, candidates sampled per AI function name.
+/// Functions not listed are requested once. Sampling only helps with a temperature above zero.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct BestOfConfig {
+    candidates: HashMap<String, usize>
+}
+
+impl BestOfConfig {
+    pub fn candidates_for(&self, ai_function: &str) -> usize {
+        self.candidates.get(ai_function).copied().unwrap_or(1).max(1)
+    }
+}
+
+/// Picks one of several answers to the same request
+#[async_trait]
+pub trait CandidateSelector: Send + Sync {
+
+    /// Shown in the logs next to the chosen candidate
+    fn name(&self) -> &str;
+
+    /// Index of the best candidate, None when no candidate is acceptable
+    async fn select(&self, candidates: &[String]) -> Result<Option<usize>, LlmError>;
+}
+
+/// Check run on a candidate, true when it is acceptable
+pub type CandidateCheck = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// First candidate that passes a check, checks run one at a time off the async runtime
+pub struct FirstPassing {
+    name: String,
+    check: CandidateCheck
+}
+
+impl FirstPassing {
+    pub fn new(name: &str, check: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        FirstPassing { name: name.to_string(), check: Arc::new(check) }
+    }
+
+    /// Writes each candidate to `code_file` in a scratch copy of the Cargo project in `project_dir` and keeps the first one
+    /// `cargo build` accepts. The project itself is never written to. Build output is shared between candidates and runs,
+    /// so only the first build compiles the dependencies. Fails right away when `project_dir` is not a Cargo project.
+    pub fn compiles(project_dir: &str, code_file: &str) -> Result<Self, LlmError> {
+        let project_dir = PathBuf::from(project_dir);
+        if !project_dir.join("Cargo.toml").is_file() {
+            return Err(LlmError::MissingConfig(format!(
+                "{} has no Cargo.toml, candidates can only be compiled in a Cargo project", project_dir.display()
+            )));
+        }
+
+        let code_file = code_file.to_string();
+        let project_name = project_dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let scratch_dir = env::temp_dir().join(format!("acadia_candidates_{}_{}", process::id(), project_name));
+        let target_dir = env::temp_dir().join("acadia_candidates_target");
+        Ok(FirstPassing::new("first that compiles", move |candidate| {
+            if copy_sources(&project_dir, &scratch_dir).is_err() || fs::write(scratch_dir.join(&code_file), candidate).is_err() {
+                return false;
+            }
+            cargo_build(&scratch_dir, Some(&target_dir)).is_ok()
+        }))
+    }
+}
+
+/// Copies everything in `from` to `to` except the build output in `target`
+fn copy_sources(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name() == "target" {
+            continue;
+        }
+        let destination = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_sources(&entry.path(), &destination)?;
+        } else {
+            fs::copy(entry.path(), destination)?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl CandidateSelector for FirstPassing {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn select(&self, candidates: &[String]) -> Result<Option<usize>, LlmError> {
+        for (index, candidate) in candidates.iter().enumerate() {
+            let check = self.check.clone();
+            let candidate = candidate.clone();
+            let passes = tokio::task::spawn_blocking(move || check(&candidate))
+                .await
+                .map_err(|e| LlmError::MalformedResponse(format!("candidate check panicked: {}", e)))?;
+            if passes {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Most frequent answer among the candidates that decode into `T`, compared as JSON so formatting does not matter.
+/// Ties go to the earliest candidate.
+pub struct MajorityVote<T> {
+    decoded_as: PhantomData<fn() -> T>
+}
+
+impl<T> MajorityVote<T> {
+    pub fn new() -> Self {
+        MajorityVote { decoded_as: PhantomData }
+    }
+}
+
+impl<T> Default for MajorityVote<T> {
+    fn default() -> Self {
+        MajorityVote::new()
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned> CandidateSelector for MajorityVote<T> {
+    fn name(&self) -> &str {
+        "majority vote"
+    }
+
+    async fn select(&self, candidates: &[String]) -> Result<Option<usize>, LlmError> {
+        let decoded: Vec<Option<Value>> = candidates
+            .iter()
+            .map(|candidate| {
+                let value: Value = decode_llm_json(candidate).ok()?;
+                serde_json::from_value::<T>(value.clone()).ok().map(|_| value)
+            })
+            .collect();
+
+        let votes = |value: &Value| decoded.iter().filter(|other| other.as_ref() == Some(value)).count();
+        Ok(decoded
+            .iter()
+            .enumerate()
+            .filter_map(|(index, value)| value.as_ref().map(|value| (index, votes(value))))
+            .fold(None, |best: Option<(usize, usize)>, (index, count)| match best {
+                Some((_, best_count)) if best_count >= count => best,
+                _ => Some((index, count))
+            })
+            .map(|(index, _)| index))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JudgeVerdict {
+    best: usize
+}
+
+/// Asks a model which candidate fits `criteria` best, the judge request is budgeted and tracked like any other
+/// and aborted when `cancel` fires
+pub struct LlmJudge {
+    provider: ProviderHandle,
+    criteria: String,
+    cancel: CancellationToken
+}
+
+impl LlmJudge {
+    pub fn new(provider: ProviderHandle, criteria: &str, cancel: CancellationToken) -> Self {
+        LlmJudge { provider, criteria: criteria.to_string(), cancel }
+    }
+
+    fn judge_messages(&self, candidates: &[String]) -> Vec<Message> {
+        let numbered: String = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| format!("CANDIDATE {}:\n{}\n", index, candidate))
+            .collect();
+
+        vec![
+            Message {
+                role: "system".to_string(),
+                content: format!(
+                    "You are judging {} candidate answers to the same request. CRITERIA: {}
+        Reply ONLY with JSON of the form {{\"best\": <number of the best candidate>}}. No commentary.",
+                    candidates.len(),
+                    self.criteria
+                ),
+                images: Vec::new()
+            },
+            Message { role: "user".to_string(), content: numbered, images: Vec::new() }
+        ]
+    }
+}
+
+#[async_trait]
+impl CandidateSelector for LlmJudge {
+    fn name(&self) -> &str {
+        "LLM judge"
+    }
+
+    async fn select(&self, candidates: &[String]) -> Result<Option<usize>, LlmError> {
+        let verdict = send_agent_messages(self.provider.clone(), self.judge_messages(candidates), "Judge", &AgentState::Working, "judge_candidates", &self.cancel).await?;
+        let verdict: JudgeVerdict = decode_llm_json(&verdict)?;
+        Ok((verdict.best < candidates.len()).then_some(verdict.best))
+    }
+}
+
+/// Asks `first`, and `then` only when `first` accepts none of the candidates
+pub struct OrElse<A, B> {
+    name: String,
+    first: A,
+    then: B
+}
+
+impl<A: CandidateSelector, B: CandidateSelector> OrElse<A, B> {
+    pub fn new(first: A, then: B) -> Self {
+        OrElse { name: format!("{}, else {}", first.name(), then.name()), first, then }
+    }
+}
+
+#[async_trait]
+impl<A: CandidateSelector, B: CandidateSelector> CandidateSelector for OrElse<A, B> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn select(&self, candidates: &[String]) -> Result<Option<usize>, LlmError> {
+        match self.first.select(candidates).await? {
+            Some(index) => Ok(Some(index)),
+            None => self.then.select(candidates).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{models::general::project::ProjectScope, providers::mock_provider::MockProvider, utils::files_io::{GENERATED_CODE_DIR, GENERATED_CODE_FILE}};
+
+    use super::*;
+
+    fn candidates(answers: &[&str]) -> Vec<String> {
+        answers.iter().map(|answer| answer.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_first_passing() {
+        let selector = FirstPassing::new("contains main", |candidate| candidate.contains("fn main"));
+
+        assert_eq!(selector.select(&candidates(&["let x = 1;", "fn main() {}", "fn main() { x }"])).await.unwrap(), Some(1));
+        assert_eq!(selector.select(&candidates(&["let x = 1;"])).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_majority_vote() {
+        let crud = r#"{"is_crud_required": true, "is_user_login": false, "is_external_urls_required": false}"#;
+        let crud_fenced = "```json\n{\"is_crud_required\":true,\"is_user_login\":false,\"is_external_urls_required\":false}\n```";
+        let login = r#"{"is_crud_required": true, "is_user_login": true, "is_external_urls_required": false}"#;
+        let selector = MajorityVote::<ProjectScope>::new();
+
+        assert_eq!(selector.select(&candidates(&[login, crud, "not json", crud_fenced])).await.unwrap(), Some(1));
+        assert_eq!(selector.select(&candidates(&[login, crud])).await.unwrap(), Some(0));
+        assert_eq!(selector.select(&candidates(&["not json", r#"{"other": 1}"#])).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_compiles_builds_a_scratch_copy() {
+        let project_dir = env::temp_dir().join("acadia_test_compiles_project");
+        let _ = fs::remove_dir_all(&project_dir);
+        fs::create_dir_all(project_dir.join("src")).unwrap();
+        fs::write(project_dir.join("Cargo.toml"), "[package]\nname = \"scratch\"\nversion = \"0.1.0\"\nedition = \"2021\"\n").unwrap();
+        fs::write(project_dir.join("src/main.rs"), "// original").unwrap();
+        let selector = FirstPassing::compiles(project_dir.to_str().unwrap(), "src/main.rs").unwrap();
+
+        let chosen = selector.select(&candidates(&["fn main() { x }", "fn main() {}"])).await.unwrap();
+
+        assert_eq!(chosen, Some(1));
+        assert_eq!(fs::read_to_string(project_dir.join("src/main.rs")).unwrap(), "// original");
+    }
+
+    #[tokio::test]
+    async fn test_compiles_the_generated_project() {
+        let code_path = Path::new(GENERATED_CODE_DIR).join(GENERATED_CODE_FILE);
+        let original = fs::read_to_string(&code_path).ok();
+        let selector = FirstPassing::compiles(GENERATED_CODE_DIR, GENERATED_CODE_FILE).unwrap();
+
+        let chosen = selector.select(&candidates(&["fn main() { undefined_function() }", "fn main() {}"])).await.unwrap();
+
+        assert_eq!(chosen, Some(1));
+        assert_eq!(fs::read_to_string(&code_path).ok(), original);
+    }
+
+    #[test]
+    fn test_compiles_needs_a_cargo_project() {
+        let project_dir = env::temp_dir().join("acadia_test_compiles_no_manifest");
+        fs::create_dir_all(&project_dir).unwrap();
+
+        let result = FirstPassing::compiles(project_dir.to_str().unwrap(), GENERATED_CODE_FILE);
+
+        assert!(matches!(result, Err(LlmError::MissingConfig(message)) if message.contains("no Cargo.toml")));
+    }
+
+    #[tokio::test]
+    async fn test_llm_judge() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("", r#"{"best": 2}"#).respond("", r#"{"best": 7}"#);
+        let judge = LlmJudge::new(mock.clone(), "the shortest correct program", CancellationToken::new());
+
+        assert_eq!(judge.select(&candidates(&["a", "b", "c"])).await.unwrap(), Some(2));
+        assert_eq!(judge.select(&candidates(&["a", "b", "c"])).await.unwrap(), None);
+        assert!(mock.requests()[0].messages[1].content.contains("CANDIDATE 2:\nc"));
+    }
+
+    #[tokio::test]
+    async fn test_or_else_asks_the_judge_when_nothing_passes() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("", r#"{"best": 1}"#);
+        let selector = OrElse::new(
+            FirstPassing::new("non empty", |candidate| !candidate.is_empty()),
+            LlmJudge::new(mock.clone(), "the longest", CancellationToken::new())
+        );
+
+        assert_eq!(selector.select(&candidates(&["", "a"])).await.unwrap(), Some(1));
+        assert!(mock.requests().is_empty());
+        assert_eq!(selector.select(&candidates(&["", ""])).await.unwrap(), Some(1));
+        assert_eq!(mock.requests().len(), 1);
+        assert_eq!(selector.name(), "non empty, else LLM judge");
+    }
+
+    #[test]
+    fn test_best_of_config() {
+        let config: BestOfConfig = serde_json::from_str(r#"{ "print_backend_webserver_code": 3, "print_project_scope": 0 }"#).unwrap();
+
+        assert_eq!(config.candidates_for("print_backend_webserver_code"), 3);
+        assert_eq!(config.candidates_for("print_project_scope"), 1);
+        assert_eq!(config.candidates_for("print_site_urls"), 1);
+    }
+}
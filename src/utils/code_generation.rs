@@ -1,9 +1,10 @@
 use dotenv::dotenv;
-use std::fs;
+
+use crate::utils::files_io::write_atomically;
 
 /// Writes LLM generated code to specified filepath
 pub fn write_to_file(content: &str, filepath: &str) {
-    fs::write(filepath, content)
+    write_atomically(filepath, content)
         .expect(format!("Failed to write to filepath: {}", filepath).as_str());
 }
 
@@ -13,17 +14,20 @@ mod tests {
     use std::env;
 
     use super::*;
-    use crate::{models::general::llm::{LLMModel, Message}, utils::llm_requests::llm_request};
+    use crate::{models::general::{generation::GenerationParams, llm::Message}, providers::provider_registry::{ProviderRegistry, LLAMA3}, utils::llm_requests::llm_completion_request};
 
     #[tokio::test]
     async fn test_write_to_file() {
         dotenv().ok();
         let msg = Message {
             role: "user".to_string(),
-            content: "Can you write a Rust application that prints a poem?".to_string()
+            content: "Can you write a Rust application that prints a poem?".to_string(),
+            images: Vec::new()
         };
-        let content: String = llm_request(vec![msg], None).await.unwrap();
-        let filepath: String = env::var("CODE_OUTPUT_PATH").expect("could not find CODE_OUTPUT_PATH");        
-        write_to_file(content.as_str(), format!("{}main.rs", filepath).as_str());
+        let content: String = llm_completion_request(vec![msg], ProviderRegistry::with_cassettes().get(LLAMA3).unwrap(), &GenerationParams::default()).await.unwrap().content;
+        let filepath = env::temp_dir().join("acadia_test_write_to_file_main.rs");
+        write_to_file(content.as_str(), filepath.to_str().unwrap());
+
+        assert_eq!(std::fs::read_to_string(&filepath).unwrap(), content);
     }
 }
\ No newline at end of file
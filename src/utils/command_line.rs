@@ -1,9 +1,9 @@
-use std::io::{stdin, stdout};
+use std::io::{stdin, stdout, Write};
 
 use crossterm::{style::{Color, ResetColor, SetForegroundColor}, ExecutableCommand};
 use dialoguer::Select;
 
-use crate::models::general::{llm::{llm_choices, LLMModel}, project::{backend_languages, frontend_language, project_focus, UserInputs}};
+use crate::{models::general::{config::AcadiaConfig, llm::llm_choices, project::{backend_languages, frontend_language, project_focus, UserInputs}}, utils::files_io::read_image_attachments};
 
 #[derive(Debug)]
 pub enum LogMessage {
@@ -17,9 +17,69 @@ impl LogMessage {
     pub fn print_message(&self, msg: &str, color: Color) {
         let mut stdout = stdout();
 
-        stdout.execute(SetForegroundColor(color));
+        let _ = stdout.execute(SetForegroundColor(color));
         println!("{}", msg);
-        stdout.execute(ResetColor);
+        let _ = stdout.execute(ResetColor);
+    }
+
+    /// Prints part of a message without a line break so streamed tokens show up as they arrive
+    pub fn print_stream_delta(&self, delta: &str, color: Color) {
+        let mut stdout = stdout();
+
+        let _ = stdout.execute(SetForegroundColor(color));
+        print!("{}", delta);
+        let _ = stdout.execute(ResetColor);
+        let _ = stdout.flush();
+    }
+}
+
+/// Flags accepted on the command line, they take precedence over `acadia.json`
+#[derive(Debug, Default, PartialEq)]
+pub struct CliArgs {
+    pub max_tokens: Option<u64>,
+    pub max_usd: Option<f64>,
+    pub no_cache: bool,
+    pub clear_cache: bool,
+    /// Reach every provider endpoint during the preflight check
+    pub ping: bool
+}
+
+impl CliArgs {
+
+    /// Parses the arguments following the program name
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut cli_args = CliArgs::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--max-tokens" => {
+                    let value = args.next().ok_or("--max-tokens expects a number of tokens")?;
+                    cli_args.max_tokens = Some(value.parse().map_err(|_| format!("Invalid --max-tokens value: {}", value))?);
+                },
+                "--max-usd" => {
+                    let value = args.next().ok_or("--max-usd expects an amount in USD")?;
+                    cli_args.max_usd = Some(value.parse().map_err(|_| format!("Invalid --max-usd value: {}", value))?);
+                },
+                "--no-cache" => cli_args.no_cache = true,
+                "--clear-cache" => cli_args.clear_cache = true,
+                "--ping" => cli_args.ping = true,
+                unknown => return Err(format!("Unknown argument: {}", unknown))
+            }
+        }
+        Ok(cli_args)
+    }
+
+    pub fn apply(&self, config: &mut AcadiaConfig) {
+        if self.max_tokens.is_some() {
+            config.budget.max_tokens = self.max_tokens;
+        }
+        if self.max_usd.is_some() {
+            config.budget.max_usd = self.max_usd;
+        }
+        if self.no_cache {
+            config.cache.enabled = false;
+        }
     }
 }
 
@@ -48,6 +108,7 @@ pub fn project_details() -> UserInputs {
     // 3. Choose backend language: (Conditional)
     // 4. Choose frontend_language: (Conditional)
     // 5. Which LLM model do you want to build this project?
+    // 6. Any UI mockups or diagrams to attach?
     let question_one = prompt_user("What project are we building?", None);
     user_inputs.project_to_build = question_one;
 
@@ -79,9 +140,33 @@ pub fn project_details() -> UserInputs {
     let llm_choice = prompt_user("Which LLM model do you want to build this project?", Some(llm_choices()));
     user_inputs.llm_model = Some(llm_choice);
 
+    user_inputs.image_paths = prompt_image_paths();
+
     user_inputs
 }
 
+/// Comma separated image paths, asked again until every file can be attached
+fn prompt_image_paths() -> Vec<String> {
+    loop {
+        let answer = prompt_user("Attach UI mockups or diagrams? Enter image paths separated by commas, or leave empty", None);
+        let image_paths = parse_image_paths(&answer);
+
+        match read_image_attachments(&image_paths) {
+            Ok(_) => return image_paths,
+            Err(e) => LogMessage::Error.print_message(&e.to_string(), Color::Red)
+        }
+    }
+}
+
+fn parse_image_paths(answer: &str) -> Vec<String> {
+    answer
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -98,6 +183,28 @@ mod tests {
         println!("Color should reset");
     }
 
+    #[test]
+    fn test_parse_cli_args() {
+        let args: Vec<String> = ["--max-tokens", "50000", "--max-usd", "2.5"].iter().map(|arg| arg.to_string()).collect();
+
+        let cli_args = CliArgs::parse(&args).unwrap();
+        let mut config = AcadiaConfig::default();
+        cli_args.apply(&mut config);
+
+        assert_eq!(config.budget.max_tokens, Some(50_000));
+        assert_eq!(config.budget.max_usd, Some(2.5));
+        assert!(CliArgs::parse(&["--ping".to_string()]).unwrap().ping);
+        assert!(CliArgs::parse(&["--no-cache".to_string()]).unwrap().no_cache);
+        assert!(CliArgs::parse(&["--max-usd".to_string()]).is_err());
+        assert!(CliArgs::parse(&["--verbose".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_image_paths() {
+        assert_eq!(parse_image_paths("wireframe.png, docs/architecture.jpg\n"), vec!["wireframe.png", "docs/architecture.jpg"]);
+        assert!(parse_image_paths("\n").is_empty());
+    }
+
     #[test]
     fn test_prompt_user() {
         let answer = prompt_user("What project are we building?", None);
@@ -1,23 +1,85 @@
-use std::fs;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{fs, io::{self, Write}, path::Path};
+
+use crate::models::general::{llm::ImageAttachment, project::ProjectSpec};
 
 // TODO: Remove
 const CODE_TEMPLATE_PATH: &str = "code_templates/";
+pub const GENERATED_CODE_DIR: &str = "generated_code";
+/// File of the generated project the backend code is written to
+pub const GENERATED_CODE_FILE: &str = "main.rs";
+
+/// Largest image the Anthropic API accepts, OpenAI allows bigger ones
+const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
 
 // Reading data
 pub fn read_code_template_contents(language: String) -> String {
+    try_read_code_template_contents(&language).expect("Failed to read code template")
+}
+
+/// Same as `read_code_template_contents` but hands back the error instead of panicking
+pub fn try_read_code_template_contents(language: &str) -> io::Result<String> {
     let mut path: String = String::from(CODE_TEMPLATE_PATH);
-    match language.as_str() {
+    match language {
         "Rust" | "rust" => path.push_str("rust_axum/main.rs"),
         _ => path.push_str("rust_axum/main.rs")
     }
     println!("{}", path);
-    fs::read_to_string(path).expect("Failed to read code template")
+    fs::read_to_string(path)
+}
+
+/// Reads an image file and base64 encodes it for a request, the media type follows the file extension
+pub fn read_image_attachment(path: &str) -> io::Result<ImageAttachment> {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    let media_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a PNG, JPEG, GIF or WebP image", path)))
+    };
+
+    let size = fs::metadata(path).map_err(|e| io::Error::new(e.kind(), format!("Could not read image {}: {}", path, e)))?.len();
+    if size > MAX_IMAGE_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is {} bytes, images may be at most {} bytes", path, size, MAX_IMAGE_BYTES)));
+    }
+
+    Ok(ImageAttachment {
+        media_type: media_type.to_string(),
+        data: STANDARD.encode(fs::read(path)?)
+    })
+}
+
+/// Every image of `paths`, failing on the first one that can not be attached
+pub fn read_image_attachments(paths: &[String]) -> io::Result<Vec<ImageAttachment>> {
+    paths.iter().map(|path| read_image_attachment(path)).collect()
 }
 
 // Writing data
-pub fn write_code_template_contents(contents: &String, language: String) {
-    let path = String::from("generated_code/main.rs");
-    fs::write(path, contents).expect("Failed to save file");
+pub fn write_code_template_contents(contents: &String, code_dir: &str) {
+    let path = format!("{}/{}", code_dir.trim_end_matches('/'), GENERATED_CODE_FILE);
+    write_atomically(&path, contents).expect("Failed to save file");
+}
+
+/// Writes to a temporary file beside `path` and renames it over `path` once flushed,
+/// so an interrupted run leaves either the old or the new contents and never half a file
+pub fn write_atomically(path: &str, contents: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, Path::new(path)).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+/// Saves the project spec beside the generated code, used to keep the work of a run that stopped early
+pub fn write_project_spec(project_spec: &ProjectSpec, dir: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let path = format!("{}/project_spec.json", dir.trim_end_matches('/'));
+    write_atomically(&path, &serde_json::to_string_pretty(project_spec)?)?;
+    Ok(path)
 }
 
 #[cfg(test)]
@@ -27,8 +89,54 @@ mod tests {
 
     #[test]
     fn test_read_write_code_file() {
+        let dir = std::env::temp_dir().join("acadia_test_read_write_code_file");
+        fs::create_dir_all(&dir).unwrap();
         let code_file = read_code_template_contents("Rust".to_string());
-        write_code_template_contents(&code_file, "Rust".to_string());
-        
+        write_code_template_contents(&code_file, dir.to_str().unwrap());
+
+        assert_eq!(fs::read_to_string(dir.join(GENERATED_CODE_FILE)).unwrap(), code_file);
+    }
+
+    #[test]
+    fn test_write_atomically_replaces_file() {
+        let dir = std::env::temp_dir().join("acadia_test_write_atomically");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        fs::write(&path, "fn main() { old() }").unwrap();
+
+        write_atomically(path.to_str().unwrap(), "fn main() {}").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {}");
+        assert!(!dir.join("main.rs.tmp").exists());
+    }
+
+    #[test]
+    fn test_read_image_attachment() {
+        let dir = std::env::temp_dir().join("acadia_test_read_image_attachment");
+        fs::create_dir_all(&dir).unwrap();
+        let mockup = dir.join("mockup.PNG");
+        let notes = dir.join("notes.txt");
+        fs::write(&mockup, [0x89, b'P', b'N', b'G']).unwrap();
+        fs::write(&notes, "not an image").unwrap();
+
+        let image = read_image_attachment(mockup.to_str().unwrap()).unwrap();
+
+        assert_eq!(image, ImageAttachment { media_type: "image/png".to_string(), data: "iVBORw==".to_string() });
+        assert_eq!(read_image_attachment(notes.to_str().unwrap()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(read_image_attachments(&[dir.join("missing.jpg").to_str().unwrap().to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_write_project_spec() {
+        let dir = std::env::temp_dir().join("acadia_test_write_project_spec");
+        fs::create_dir_all(&dir).unwrap();
+        let mut project_spec = ProjectSpec::new();
+        project_spec.project_description = Some("build a website that tracks todo items".to_string());
+
+        let path = write_project_spec(&project_spec, dir.to_str().unwrap()).unwrap();
+        let saved: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+
+        assert_eq!(saved["project_description"], "build a website that tracks todo items");
+        assert!(saved["backend_code"].is_null());
     }
 }
\ No newline at end of file
@@ -1,6 +1,40 @@
 use reqwest::Client;
+use std::{path::Path, process::{Command, Stdio}, time::Duration};
 
-pub async fn check_url_status_code(client: &Client, url: &str) -> Result<u16, reqwest::Error> {
-    let res = client.get(url).send().await?;
+/// Longest an external url may take to answer before it counts as unreachable
+pub const EXTERNAL_URL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lines of compiler output kept from a failed build, the first errors are the ones worth fixing
+const BUILD_ERROR_LINES: usize = 60;
+
+/// `timeout` caps the whole request, on top of the client's connect and read timeouts
+pub async fn check_url_status_code(client: &Client, url: &str, timeout: Duration) -> Result<u16, reqwest::Error> {
+    let res = client.get(url).timeout(timeout).send().await?;
     Ok(res.status().as_u16())
-}
\ No newline at end of file
+}
+
+/// Runs `cargo build` on the project in `project_dir`, a failed build hands back the start of the compiler errors.
+/// The manifest is passed explicitly so cargo never falls back to a project in a parent directory.
+/// Build output goes to `target_dir` when given, the project's own `target` otherwise.
+pub fn cargo_build(project_dir: &Path, target_dir: Option<&Path>) -> Result<(), String> {
+    let manifest = project_dir.join("Cargo.toml");
+    if !manifest.is_file() {
+        return Err(format!("{} is not a Cargo project, it has no Cargo.toml", project_dir.display()));
+    }
+
+    let mut command = Command::new("cargo");
+    command.args(["build", "--message-format", "short", "--manifest-path"]).arg(&manifest);
+    if let Some(target_dir) = target_dir {
+        command.arg("--target-dir").arg(target_dir);
+    }
+    let output = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("could not run cargo build in {}: {}", project_dir.display(), e))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(String::from_utf8_lossy(&output.stderr).lines().take(BUILD_ERROR_LINES).collect::<Vec<_>>().join("\n"))
+}
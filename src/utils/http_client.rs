@@ -0,0 +1,92 @@
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use serde::Deserialize;
+use std::{fs, sync::OnceLock, time::Duration};
+
+use crate::models::general::{config::AcadiaConfig, llm_error::LlmError};
+
+static CLIENT: OnceLock<Result<Client, String>> = OnceLock::new();
+
+/// `http` section of `acadia.json`, applies to every provider and to the external url checks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Proxy for every request, e.g. "http://proxy.corp:3128". The `HTTPS_PROXY` env variables apply when unset.
+    pub proxy: Option<String>,
+    /// Comma separated hosts that bypass `proxy`, e.g. "localhost,.corp"
+    pub no_proxy: Option<String>,
+    /// PEM files of extra root certificates, e.g. an internal CA bundle
+    pub ca_certificates: Vec<String>,
+    pub connect_timeout_secs: u64,
+    /// Longest wait between two reads of a response, streamed answers can take longer than this in total
+    pub read_timeout_secs: u64,
+    pub pool_idle_timeout_secs: u64,
+    pub pool_max_idle_per_host: usize
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            proxy: None,
+            no_proxy: None,
+            ca_certificates: Vec::new(),
+            connect_timeout_secs: 10,
+            read_timeout_secs: 120,
+            pool_idle_timeout_secs: 90,
+            pool_max_idle_per_host: 8
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn build_client(&self) -> Result<Client, LlmError> {
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .read_timeout(Duration::from_secs(self.read_timeout_secs))
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout_secs))
+            .pool_max_idle_per_host(self.pool_max_idle_per_host);
+
+        if let Some(proxy_url) = &self.proxy {
+            let proxy = Proxy::all(proxy_url)
+                .map_err(|e| LlmError::MissingConfig(format!("http.proxy {} is not a valid proxy url: {}", proxy_url, e)))?;
+            builder = builder.proxy(proxy.no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string)));
+        }
+
+        for path in &self.ca_certificates {
+            let pem = fs::read(path)
+                .map_err(|e| LlmError::MissingConfig(format!("could not read CA certificate {}: {}", path, e)))?;
+            let certificates = Certificate::from_pem_bundle(&pem)
+                .map_err(|e| LlmError::MissingConfig(format!("{} is not a PEM certificate bundle: {}", path, e)))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        builder.build().map_err(|e| LlmError::MissingConfig(format!("could not build the HTTP client: {}", e)))
+    }
+}
+
+/// Client shared by every request of the run so connections are pooled, built from the `http` config on first use
+pub fn shared_client() -> Result<Client, LlmError> {
+    CLIENT
+        .get_or_init(|| AcadiaConfig::global().http.build_client().map_err(|e| e.to_string()))
+        .clone()
+        .map_err(LlmError::MissingConfig)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_build_client_from_config() {
+        let config: HttpConfig = serde_json::from_str(r#"{ "proxy": "http://proxy.corp:3128", "no_proxy": "localhost", "read_timeout_secs": 30 }"#).unwrap();
+        let missing_ca = HttpConfig { ca_certificates: vec!["/nonexistent/corp-ca.pem".to_string()], ..HttpConfig::default() };
+        let bad_proxy = HttpConfig { proxy: Some("not a url".to_string()), ..HttpConfig::default() };
+
+        assert_eq!(config.connect_timeout_secs, 10);
+        assert!(config.build_client().is_ok());
+        assert!(matches!(missing_ca.build_client(), Err(LlmError::MissingConfig(msg)) if msg.contains("corp-ca.pem")));
+        assert!(bad_proxy.build_client().is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::models::general::llm_error::LlmError;
+
+/// `decode` section of `acadia.json`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DecodePolicy {
+    /// Answers requested in total before a decode error is returned, 1 disables the correction loop
+    pub max_attempts: u32
+}
+
+impl Default for DecodePolicy {
+    fn default() -> Self {
+        DecodePolicy { max_attempts: 3 }
+    }
+}
+
+/// Content of the first markdown code fence, the language tag is dropped.
+/// An unterminated fence runs to the end of the text.
+fn strip_code_fence(text: &str) -> Option<&str> {
+    let (_, after_fence) = text.split_once("```")?;
+    let (_, content) = after_fence.split_once('\n')?;
+    Some(content.split_once("```").map_or(content, |(inner, _)| inner))
+}
+
+/// First JSON object or array in `text`, surrounding prose is ignored.
+/// An unbalanced value runs to the end of the text and is left for `repair_json` to close.
+pub fn extract_json(text: &str) -> Option<&str> {
+    let start = text.find(['{', '['])?;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (offset, c) in text[start..].char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[start..=start + offset]);
+                }
+            },
+            _ => {}
+        }
+    }
+    Some(&text[start..])
+}
+
+/// True when the last written character ends a value, so a new value needs a comma in front of it
+fn ends_value(out: &str) -> bool {
+    out.trim_end().chars().last().is_some_and(|c| matches!(c, '"' | '}' | ']') || c.is_ascii_alphanumeric())
+}
+
+/// Fixes the defects LLMs commonly put in JSON: trailing and missing commas, `//` comments,
+/// Python literals, type names in front of values (`bool true`), raw newlines in strings and unclosed brackets.
+pub fn repair_json(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    let mut closers: Vec<char> = Vec::new();
+    let mut chars = json.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                if ends_value(&out) {
+                    out.push(',');
+                }
+                out.push('"');
+                let mut escaped = false;
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    match c {
+                        _ if escaped => { escaped = false; out.push(c); },
+                        '\\' => { escaped = true; out.push(c); },
+                        '"' => { closed = true; out.push(c); break; },
+                        '\n' => out.push_str("\\n"),
+                        '\r' => {},
+                        _ => out.push(c)
+                    }
+                }
+                if !closed {
+                    out.push('"');
+                }
+            },
+            '{' | '[' => {
+                if ends_value(&out) {
+                    out.push(',');
+                }
+                closers.push(if c == '{' { '}' } else { ']' });
+                out.push(c);
+            },
+            '}' | ']' => {
+                if out.trim_end().ends_with(',') {
+                    out.truncate(out.trim_end().len() - 1);
+                }
+                closers.pop();
+                out.push(c);
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            },
+            c if c.is_ascii_alphanumeric() || c == '-' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-' | '_')) {
+                    word.push(c);
+                }
+                let literal = match word.as_str() {
+                    "True" => "true",
+                    "False" => "false",
+                    "None" => "null",
+                    "bool" | "string" | "number" => continue,
+                    other => other
+                };
+                if ends_value(&out) {
+                    out.push(',');
+                }
+                out.push_str(literal);
+            },
+            _ => out.push(c)
+        }
+    }
+
+    if out.trim_end().ends_with(',') {
+        out.truncate(out.trim_end().len() - 1);
+    }
+    while let Some(closer) = closers.pop() {
+        out.push(closer);
+    }
+    out
+}
+
+/// Deserializes an LLM answer, falling back to the first fenced or embedded JSON value and repairing it.
+/// When every attempt fails the error carries the untouched answer.
+pub fn decode_llm_json<T: DeserializeOwned>(raw: &str) -> Result<T, LlmError> {
+    let error = match serde_json::from_str(raw.trim()) {
+        Ok(value) => return Ok(value),
+        Err(e) => e
+    };
+
+    let Some(candidate) = extract_json(strip_code_fence(raw).unwrap_or(raw)) else {
+        return Err(LlmError::Decode { error: error.to_string(), raw: raw.to_string() });
+    };
+    if let Ok(value) = serde_json::from_str(candidate) {
+        return Ok(value);
+    }
+
+    serde_json::from_str(&repair_json(candidate))
+        .map_err(|e| LlmError::Decode { error: e.to_string(), raw: raw.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::models::general::project::ProjectScope;
+
+    use super::*;
+
+    #[test]
+    fn test_extract_json_from_prose_and_fences() {
+        let answer = "Sure! Here are the urls:\n```json\n[\"https://api.binance.com/api/v3/exchangeInfo\"]\n```\nLet me know [if] you need more.";
+
+        assert_eq!(extract_json(strip_code_fence(answer).unwrap()), Some("[\"https://api.binance.com/api/v3/exchangeInfo\"]"));
+        assert_eq!(extract_json("The scope is {\"a\": \"}\"} as requested"), Some("{\"a\": \"}\"}"));
+        assert_eq!(extract_json("no json here"), None);
+    }
+
+    #[test]
+    fn test_repair_json_defects() {
+        assert_eq!(repair_json("[\"a\", \"b\",]"), "[\"a\", \"b\"]");
+        assert_eq!(repair_json("{\"a\": True, // comment\n \"b\": None}"), "{\"a\": true, \n \"b\": null}");
+        assert_eq!(repair_json("{\"a\": [1, 2"), "{\"a\": [1, 2]}");
+        assert_eq!(repair_json("{\"text\": \"line one\nline two\"}"), "{\"text\": \"line one\\nline two\"}");
+    }
+
+    #[test]
+    fn test_decode_project_scope_docstring_example() {
+        // The example printed in the `print_project_scope` docstring, no commas and type names before values
+        let answer = "{\n  \"is_crud_required\": true\n  \"is_user_login\": false\n  \"is_external_urls_required\": bool false\n}";
+
+        let scope: ProjectScope = decode_llm_json(answer).unwrap();
+
+        assert_eq!(scope, ProjectScope { is_crud_required: true, is_user_login: false, is_external_urls_required: false });
+    }
+
+    #[test]
+    fn test_decode_failure_keeps_raw_text() {
+        let res = decode_llm_json::<Vec<String>>("Sorry, I can not list any urls");
+
+        match res {
+            Err(LlmError::Decode { raw, .. }) => assert_eq!(raw, "Sorry, I can not list any urls"),
+            other => panic!("Expected decode error, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use serde::de::{self, value::Error, DeserializeSeed, Deserializer, IntoDeserializer, Visitor};
+use serde_json::{json, Map, Value};
+
+/// Nested types deeper than this are shown as "..." so recursive types terminate
+const MAX_DEPTH: usize = 8;
+
+/// Example JSON shape of `T`, built by watching what its `Deserialize` impl asks for.
+/// Leaves hold type names, e.g. `{"is_crud_required": "bool"}` for `ProjectScope` or `["string"]` for `Vec<String>`.
+pub fn schema_of<T: de::DeserializeOwned>() -> Value {
+    let mut shape = Value::Null;
+    // Validation inside the `Deserialize` impl may reject the placeholder values, the shape is recorded before that
+    let _ = T::deserialize(SchemaTracer { shape: &mut shape, depth: 0 });
+    shape
+}
+
+struct SchemaTracer<'a> {
+    shape: &'a mut Value,
+    depth: usize
+}
+
+impl<'a> SchemaTracer<'a> {
+    fn leaf(self, name: &str) -> Self {
+        *self.shape = json!(name);
+        self
+    }
+}
+
+/// Hands out one traced element for sequences, or every field of a struct
+struct CompoundTracer<'a> {
+    shape: &'a mut Value,
+    depth: usize,
+    fields: Vec<String>,
+    next: usize
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for CompoundTracer<'a> {
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>, Error> {
+        if self.next > 0 {
+            return Ok(None);
+        }
+        self.next += 1;
+
+        let mut element = Value::Null;
+        let value = seed.deserialize(SchemaTracer { shape: &mut element, depth: self.depth + 1 });
+        *self.shape = json!([element]);
+        value.map(Some)
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for CompoundTracer<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.fields.get(self.next) {
+            Some(field) => seed.deserialize(field.clone().into_deserializer()).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let mut field_shape = Value::Null;
+        let value = seed.deserialize(SchemaTracer { shape: &mut field_shape, depth: self.depth + 1 });
+
+        if let Value::Object(object) = self.shape {
+            object.insert(self.fields[self.next].clone(), field_shape);
+        }
+        self.next += 1;
+        value
+    }
+}
+
+impl<'a> SchemaTracer<'a> {
+    fn compound(self, fields: Vec<String>) -> CompoundTracer<'a> {
+        if !fields.is_empty() {
+            *self.shape = Value::Object(Map::new());
+        }
+        CompoundTracer { shape: self.shape, depth: self.depth, fields, next: 0 }
+    }
+}
+
+macro_rules! trace_leaf {
+    ($($method:ident => $name:literal, $visit:ident($($value:expr)?);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.leaf($name);
+                visitor.$visit($($value)?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for SchemaTracer<'a> {
+    type Error = Error;
+
+    trace_leaf! {
+        deserialize_bool => "bool", visit_bool(false);
+        deserialize_i8 => "integer", visit_i8(0);
+        deserialize_i16 => "integer", visit_i16(0);
+        deserialize_i32 => "integer", visit_i32(0);
+        deserialize_i64 => "integer", visit_i64(0);
+        deserialize_u8 => "integer", visit_u8(0);
+        deserialize_u16 => "integer", visit_u16(0);
+        deserialize_u32 => "integer", visit_u32(0);
+        deserialize_u64 => "integer", visit_u64(0);
+        deserialize_f32 => "number", visit_f32(0.0);
+        deserialize_f64 => "number", visit_f64(0.0);
+        deserialize_char => "string", visit_char(' ');
+        deserialize_str => "string", visit_str("");
+        deserialize_string => "string", visit_string(String::new());
+        deserialize_bytes => "bytes", visit_bytes(&[]);
+        deserialize_byte_buf => "bytes", visit_byte_buf(Vec::new());
+        deserialize_unit => "null", visit_unit();
+        deserialize_identifier => "string", visit_str("");
+        deserialize_any => "any", visit_unit();
+        deserialize_ignored_any => "any", visit_unit();
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.depth >= MAX_DEPTH {
+            self.leaf("...");
+            return visitor.visit_seq(de::value::SeqDeserializer::<std::iter::Empty<()>, Error>::new(std::iter::empty()));
+        }
+        *self.shape = json!([]);
+        visitor.visit_seq(self.compound(Vec::new()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.depth >= MAX_DEPTH {
+            return visitor.visit_map(self.leaf("...").compound(Vec::new()));
+        }
+        visitor.visit_map(self.compound(vec!["<key>".to_string()]))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        if self.depth >= MAX_DEPTH {
+            return visitor.visit_map(self.leaf("...").compound(Vec::new()));
+        }
+        visitor.visit_map(self.compound(fields.iter().map(|field| field.to_string()).collect()))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        *self.shape = json!(format!("one of {}", variants.join(" | ")));
+        let first_variant = variants.first().copied().unwrap_or_default();
+        visitor.visit_enum(first_variant.into_deserializer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use crate::models::general::project::ProjectScope;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Nested {
+        name: Option<String>,
+        scores: HashMap<String, f64>,
+        scopes: Vec<ProjectScope>
+    }
+
+    #[test]
+    fn test_schema_of_agent_types() {
+        assert_eq!(schema_of::<Vec<String>>(), json!(["string"]));
+        assert_eq!(
+            schema_of::<ProjectScope>(),
+            json!({"is_crud_required": "bool", "is_user_login": "bool", "is_external_urls_required": "bool"})
+        );
+    }
+
+    #[test]
+    fn test_schema_of_nested_type() {
+        assert_eq!(
+            schema_of::<Nested>(),
+            json!({
+                "name": "string",
+                "scores": {"<key>": "number"},
+                "scopes": [{"is_crud_required": "bool", "is_user_login": "bool", "is_external_urls_required": "bool"}]
+            })
+        );
+    }
+}
@@ -1,192 +1,557 @@
-#![allow(dead_code)]
 use crossterm::style::Color;
-use dotenv::dotenv;
-use reqwest::{header::{HeaderMap, HeaderValue}, Client};
+use futures_util::{future::join_all, StreamExt};
 use serde::de::DeserializeOwned;
-use std::env;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
 
-use crate::{agents::base::agent_traits::AgentState, models::general::llm::{LLMModel, LLMRequestBody, LLMResponse, Message, OpenAIResponse}, utils::command_line::LogMessage};
+use crate::{agents::base::agent_traits::AgentState, models::general::{config::AcadiaConfig, generation::GenerationParams, llm::{ImageAttachment, LLMCompletion, LLMStreamEvent, Message, TokenUsage}, llm_error::LlmError}, providers::{model_router::route_chain, provider_traits::{LLMStream, ProviderHandle}}, utils::{audit_log::{audit_exchange, AuditRecord}, cancellation::cancellable, candidate_selection::CandidateSelector, command_line::LogMessage, json_repair::decode_llm_json, json_schema::schema_of, llm_retry::with_fallback, rate_limiter::throttle, token_counting::fit_context_window, response_cache::ResponseCache, usage_tracking::{record_usage, reserve_budget}}};
 
-/// Main way to interface with local LLM
-pub async fn llm_request(messages: Vec<Message>, model: Option<LLMModel>) -> Result<String, Box<dyn std::error::Error + Send>> {
-    // load .env file 
-    dotenv().ok();
-    
-    let url: String;
+/// Sends the messages to one provider as they are, without the cache, budget check or retries
+pub async fn llm_completion_request(messages: Vec<Message>, provider: ProviderHandle, params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+    println!("{} selected!", provider.name());
 
-    // Create the headers
-    let mut header_map = HeaderMap::new();
+    provider.send_messages(messages, params).await
+}
 
-    match &model {
-        Some(LLMModel::GPT4o(model_info)) => {
-            println!("{} selected!", model_info);
+/// Streams the answer of an LLM provider as it is generated
+pub async fn llm_request_stream(messages: Vec<Message>, provider: ProviderHandle, params: &GenerationParams) -> Result<LLMStream, LlmError> {
+    println!("{} selected!", provider.name());
 
-            url = env::var("OPEN_AI_URL").expect("GPT4o variable not found in .env file");
-            
-            // OPENAI creds
-            let key: String = env::var("OPEN_AI_KEY").expect("Could not find OPENAI key from .env file");
-            let org: String = env::var("OPEN_AI_ORG").expect("Could not find OPENAI org from .env file");
+    provider.stream_messages(messages, params).await
+}
 
-            header_map.insert("authorization",
-                HeaderValue::from_str(&format!("Bearer {}", key).as_str())
-                .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })? // propagate error up if any encountered
-            );
 
-            header_map.insert("OpenAI-Organization",
-                HeaderValue::from_str(&org)
-                .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?
-            );
-        },
-        Some(LLMModel::Llama3(model_info)) => {
-            println!("{} selected!", model_info);
+/// Converts function structure to static string reference for request payload.
+/// The function printer instructions go in a system message and the function input in a user message,
+/// so providers that keep the system prompt apart from the conversation still get a user turn.
+pub fn api_instruction_wrapper(func: fn(&str) -> &'static str, user_input: &str) -> Vec<Message> {
+    // Only works with the procedural macro to convert function into string reference
+    let ai_function: &str = func(user_input);
 
-            url = env::var("LLM_URL").expect("LLM_URL variable not found in .env file");
+    // LLM instructions
+    let instructions: String = format!(
+        "FUNCTION: {}
+        INSTRUCTION: You are a function printer, you ONLY print the results of functions
+        and NOTHING else. No commentary.
+        ",
+        ai_function
+    );
+    let function_input: String = format!(
+        "Here is the input of the function: {}.
+        Print out what the function will return.
+        ",
+        user_input
+    );
 
-            header_map.insert(
-                "Content-Type",
-                HeaderValue::from_str("application/json")
-                .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?
-            );
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: instructions,
+            images: Vec::new()
         },
-        None => {
-            println!("No LLM model specified. Llama3 will be used as default");
+        Message {
+            role: "user".to_string(),
+            content: function_input,
+            images: Vec::new()
+        }
+    ]
+}
 
-            url = env::var("LLM_URL").expect("LLM_URL variable not found in .env file");
+/// Adds `images` to the last user message, which carries the function input
+pub fn attach_images(mut messages: Vec<Message>, images: &[ImageAttachment]) -> Vec<Message> {
+    if let Some(message) = messages.iter_mut().rev().find(|message| message.role == "user") {
+        message.images.extend_from_slice(images);
+    }
+    messages
+}
 
-            header_map.insert(
-                "Content-Type",
-                HeaderValue::from_str("application/json")
-                .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?
-            );
+/// Requests with images are only sent to providers whose model can read them
+pub fn require_vision(provider: &ProviderHandle, messages: &[Message]) -> Result<(), LlmError> {
+    if messages.iter().any(|message| !message.images.is_empty()) && !provider.supports_vision() {
+        return Err(LlmError::VisionUnsupported { provider: provider.name().to_string(), model: provider.model() });
+    }
+    Ok(())
+}
+
+/// Looks up a previous answer to the same request when the response cache is enabled
+fn cached_completion(provider: &ProviderHandle, req_msgs: &[Message], params: &GenerationParams) -> (Option<ResponseCache>, String, Option<LLMCompletion>) {
+    let cache = ResponseCache::from_config(&AcadiaConfig::global().cache);
+    let key = ResponseCache::key(provider.name(), &provider.model(), req_msgs, params);
+    let cached = cache.as_ref().and_then(|cache| cache.get(&key));
+
+    if cached.is_some() {
+        LogMessage::Info.print_message(&format!("Cache hit for {} ({})", provider.name(), &key[..12]), Color::Rgb { r: 120, g: 200, b: 255 });
+    }
+    (cache, key, cached)
+}
+
+fn store_completion(cache: Option<ResponseCache>, key: &str, completion: &LLMCompletion) {
+    if let Some(cache) = cache {
+        if let Err(e) = cache.put(key, completion) {
+            LogMessage::Error.print_message(&format!("Could not cache the LLM response: {}", e), Color::Yellow);
         }
     }
-    
-    let client: Client = Client::builder()
-        .default_headers(header_map)
-        .build()
-        .map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?;
+}
 
+/// `images` are attached to the function input, the request fails with `LlmError::VisionUnsupported`
+/// when they are given to a provider without vision support
+#[allow(clippy::too_many_arguments)]
+pub async fn make_llm_request(
+    provider: ProviderHandle,
+    ai_func: fn(&str) -> &'static str,
+    user_req: String,
+    images: &[ImageAttachment],
+    agent_position: &str,
+    agent_state: &AgentState,
+    agent_operation: &str,
+    cancel: &CancellationToken
+) -> Result<String, LlmError> {
     
-    // let llm_req_body: LLMPayloadType;
-    // let llm_req_body = LLMPayloadType::Llama3(LLMRequestBody::new(prompt, 50, None));
-    let generated_text: String;
-    let res: LLMResponse;
-    let llm_req_body: LLMRequestBody;
-
-    match &model {
-        Some(LLMModel::GPT4o(_)) => {   
-            let model = env::var("LLM_MODEL").expect("LLM_MODEL not found in .env file");
-            llm_req_body = LLMRequestBody::new(messages, model);
-            // println!("{:?}", openai_req_body);
-
-            let res: OpenAIResponse = client
-                .post(&url)
-                .json(&llm_req_body)
-                .send()
-                .await.map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?
-                .json()
-                .await.map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?;
-            generated_text = res.choices[0].message.content.clone();
-        },
-        _ => {     
-            llm_req_body = LLMRequestBody::new(messages, "Llama3".to_owned());
-
-            res = client
-                .post(&url)
-                .json(&llm_req_body)
-                .send()
-                .await.map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?
-                .json()
-                .await.map_err(|e| -> Box<dyn std::error::Error + Send> { Box::new(e) })?;
-        
-            generated_text = res.generated_text.to_owned();
-        }
+    let req_msgs: Vec<Message> = attach_images(api_instruction_wrapper(ai_func, &user_req), images);
+    LogMessage::Info.print_message(
+        &format!("Agent: {} | State: {:?} | Performing: {}", agent_position, agent_state, agent_operation), 
+        Color::Rgb { r: 219, g: 255, b: 51 }
+    );
+
+    send_agent_messages(provider, req_msgs, agent_position, agent_state, agent_operation, cancel).await
+}
+
+/// Sends an agent conversation through the cache, budget check and retries, and records its usage.
+/// Cancelling the run aborts the request, retries and fallbacks included.
+pub async fn send_agent_messages(
+    provider: ProviderHandle,
+    req_msgs: Vec<Message>,
+    agent_position: &str,
+    agent_state: &AgentState,
+    agent_operation: &str,
+    cancel: &CancellationToken
+) -> Result<String, LlmError> {
+    let providers = route_chain(provider, agent_position, agent_operation)?;
+    let params = AcadiaConfig::global().generation.params_for(agent_operation);
+    let (cache, key, cached) = cached_completion(&providers[0], &req_msgs, &params);
+    if let Some(completion) = cached {
+        audit_exchange(&AuditRecord::cached(agent_position, agent_state, agent_operation, &req_msgs, &completion));
+        return Ok(completion.content);
     }
 
-    Ok(generated_text)
+    let completion = cancellable(cancel, send_uncached(&providers, &req_msgs, &params, agent_position, agent_state, agent_operation)).await?;
+    store_completion(cache, &key, &completion);
+    Ok(completion.content)
 }
 
+/// Budget check, rate limits, retries and fallbacks of one request, records its usage and audits every attempt
+async fn send_uncached(
+    providers: &[ProviderHandle],
+    req_msgs: &[Message],
+    params: &GenerationParams,
+    agent_position: &str,
+    agent_state: &AgentState,
+    agent_operation: &str
+) -> Result<LLMCompletion, LlmError> {
+    let _reservation = reserve_budget(&providers[0].model(), req_msgs, params)?;
+    let retry_policy = &AcadiaConfig::global().retry;
+    let completion = with_fallback(providers, retry_policy, agent_position, agent_operation, |provider| {
+        let messages = req_msgs.to_vec();
+        async move {
+            let model = provider.model();
+            require_vision(&provider, &messages)?;
+            let params = fit_context_window(&model, &messages, params)?;
+            let permit = throttle(provider.name(), &model, &messages, agent_position, agent_operation).await;
+            let started = Instant::now();
+            let res = llm_completion_request(messages.clone(), provider.clone(), &params).await;
+            audit_exchange(&AuditRecord::new(agent_position, agent_state, agent_operation, (provider.name(), &model), &messages, res.as_ref(), started.elapsed()));
+            let completion = res?;
+            if let Some(permit) = permit {
+                permit.settle(completion.usage);
+            }
+            Ok(completion)
+        }
+    }).await?;
 
-/// Converts function structure to static string reference for request payload
-fn api_instruction_wrapper(func: fn(&str) -> &'static str, user_input: &str) -> Message {
-    // Only works with the procedural macro to convert function into string reference
-    let ai_function: &str = func(user_input);
+    record_usage(&completion, agent_position, agent_operation);
+    Ok(completion)
+}
 
-    // LLM instructions
-    let content: String = format!(
-        "FUNCTION: {}
-        INSTRUCTION: You are a function printer, you ONLY print the results of functions
-        and NOTHING else. No commentary. Here is the input of the function: {}.
-        Print out what the function will return.
-        ",
-        ai_function, user_input
+/// Same as `make_llm_request` but renders the answer in the terminal while it is being generated.
+/// The assembled answer is returned once the stream ends.
+pub async fn make_llm_request_streamed(
+    provider: ProviderHandle,
+    ai_func: fn(&str) -> &'static str,
+    user_req: String,
+    agent_position: &str,
+    agent_state: &AgentState,
+    agent_operation: &str,
+    cancel: &CancellationToken
+) -> Result<String, LlmError> {
+
+    let req_msgs: Vec<Message> = api_instruction_wrapper(ai_func, &user_req);
+    LogMessage::Info.print_message(
+        &format!("Agent: {} | State: {:?} | Performing: {}", agent_position, agent_state, agent_operation),
+        Color::Rgb { r: 219, g: 255, b: 51 }
     );
 
+    let providers = route_chain(provider, agent_position, agent_operation)?;
+    let params = AcadiaConfig::global().generation.params_for(agent_operation);
+    let (cache, key, cached) = cached_completion(&providers[0], &req_msgs, &params);
+    if let Some(completion) = cached {
+        LogMessage::Info.print_message(&completion.content, Color::Rgb { r: 200, g: 200, b: 200 });
+        audit_exchange(&AuditRecord::cached(agent_position, agent_state, agent_operation, &req_msgs, &completion));
+        return Ok(completion.content);
+    }
+
+    let _reservation = reserve_budget(&providers[0].model(), &req_msgs, &params)?;
+
+    // Only opening the stream is retried or falls back, tokens already rendered can not be taken back
+    let retry_policy = &AcadiaConfig::global().retry;
+    let params = &params;
+    let (provider, mut llm_stream, permit, started) = cancellable(cancel, with_fallback(&providers, retry_policy, agent_position, agent_operation, |provider| {
+        let messages = req_msgs.clone();
+        async move {
+            // The permit is held until the stream ends so it counts as in flight
+            let model = provider.model();
+            require_vision(&provider, &messages)?;
+            let params = fit_context_window(&model, &messages, params)?;
+            let permit = throttle(provider.name(), &model, &messages, agent_position, agent_operation).await;
+            let started = Instant::now();
+            match llm_request_stream(messages.clone(), provider.clone(), &params).await {
+                Ok(llm_stream) => Ok((provider, llm_stream, permit, started)),
+                Err(e) => {
+                    audit_exchange(&AuditRecord::new(agent_position, agent_state, agent_operation, (provider.name(), &model), &messages, Err(&e), started.elapsed()));
+                    Err(e)
+                }
+            }
+        }
+    })).await?;
+
+    let mut generated_text = String::new();
+    let mut usage: Option<TokenUsage> = None;
+    // Dropping the stream on cancellation closes the connection, the partial answer is discarded
+    while let Some(event) = cancellable(cancel, async { Ok(llm_stream.next().await) }).await.inspect_err(|_| println!())? {
+        let event = event.inspect_err(|e| {
+            audit_exchange(&AuditRecord::new(agent_position, agent_state, agent_operation, (provider.name(), &provider.model()), &req_msgs, Err(e), started.elapsed()));
+        })?;
+        match event {
+            LLMStreamEvent::Delta(delta) => {
+                LogMessage::Info.print_stream_delta(&delta, Color::Rgb { r: 200, g: 200, b: 200 });
+                generated_text.push_str(&delta);
+            },
+            LLMStreamEvent::Usage(latest_usage) => usage.get_or_insert_with(TokenUsage::default).update(latest_usage)
+        }
+    }
+    println!();
+    if let Some(permit) = permit {
+        permit.settle(usage);
+    }
+
+    let completion = LLMCompletion {
+        content: generated_text,
+        provider: provider.name().to_string(),
+        model: provider.model(),
+        usage
+    };
+    audit_exchange(&AuditRecord::new(agent_position, agent_state, agent_operation, (provider.name(), &completion.model), &req_msgs, Ok(&completion), started.elapsed()));
+    record_usage(&completion, agent_position, agent_operation);
+    store_completion(cache, &key, &completion);
+    Ok(completion.content)
+}
+
+/// Follow-up asking the model to fix an answer that could not be deserialized
+fn correction_message(error: &str, schema: &serde_json::Value) -> Message {
     Message {
-        role: "system".to_string(),
-        content
+        role: "user".to_string(),
+        content: format!(
+            "Your previous answer could not be parsed as JSON: {}.
+        Expected schema: {}
+        Print the answer again as valid JSON matching the schema, and NOTHING else.
+        ",
+            error,
+            schema
+        ),
+        images: Vec::new()
     }
 }
 
-pub async fn make_llm_request(
-    llm_model: LLMModel,
+/// Same as `make_llm_request` but deserializes the answer into `T`.
+/// Answers that still fail to parse after repair are sent back to the model together with the serde error
+/// and the schema of `T`, up to `decode.max_attempts` times in total.
+#[allow(clippy::too_many_arguments)]
+pub async fn make_llm_request_decoded<T: DeserializeOwned>(
+    provider: ProviderHandle,
     ai_func: fn(&str) -> &'static str,
     user_req: String,
+    images: &[ImageAttachment],
     agent_position: &str,
     agent_state: &AgentState,
-    agent_operation: &str
-) -> String {
-    
-    let req_str: Message = api_instruction_wrapper(ai_func, &user_req);
+    agent_operation: &str,
+    cancel: &CancellationToken
+) -> Result<T, LlmError> {
+    let mut req_msgs: Vec<Message> = attach_images(api_instruction_wrapper(ai_func, &user_req), images);
     LogMessage::Info.print_message(
-        &format!("Agent: {} | State: {:?} | Performing: {}", agent_position, agent_state, agent_operation), 
+        &format!("Agent: {} | State: {:?} | Performing: {}", agent_position, agent_state, agent_operation),
         Color::Rgb { r: 219, g: 255, b: 51 }
     );
 
-    // LLMModel::GPT4o("GPT4o".to_string())
-    let detailed_llm_res = llm_request(vec![req_str], Some(llm_model)).await;
-
-    match detailed_llm_res {
-        Ok(res) => res,
-        Err(e) => {
-            panic!("Error occurred when calling LLM service: {}", e);
+    let max_attempts = AcadiaConfig::global().decode.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let llm_res = send_agent_messages(provider.clone(), req_msgs.clone(), agent_position, agent_state, agent_operation, cancel).await?;
+
+        match decode_llm_json(&llm_res) {
+            Err(LlmError::Decode { error, raw }) if attempt < max_attempts => {
+                LogMessage::Error.print_message(
+                    &format!("Agent: {} | Performing: {} | Answer {}/{} is not valid JSON: {} | Asking for a correction", agent_position, agent_operation, attempt, max_attempts, error),
+                    Color::Yellow
+                );
+                req_msgs.push(Message { role: "assistant".to_string(), content: raw, images: Vec::new() });
+                req_msgs.push(correction_message(&error, &schema_of::<T>()));
+                attempt += 1;
+            },
+            decoded => return decoded
         }
     }
 }
 
-pub async fn make_llm_request_decoded<T: DeserializeOwned>(
-    llm_model: LLMModel,
+/// Same as `make_llm_request` but asks for `candidates` answers in parallel and keeps the one `selector` picks.
+/// The number of candidates per AI function is set in the `best_of` section of the config.
+/// Candidates skip the response cache so they can differ, a configured seed is offset per candidate.
+/// Failed candidates are dropped, the first candidate is used when the selector accepts none.
+/// Every candidate reserves its estimated cost first, candidates that would go over the run budget fail.
+#[allow(clippy::too_many_arguments)]
+pub async fn make_llm_request_best_of(
+    provider: ProviderHandle,
     ai_func: fn(&str) -> &'static str,
     user_req: String,
+    images: &[ImageAttachment],
     agent_position: &str,
     agent_state: &AgentState,
-    agent_operation: &str
-) -> T {
-    let llm_res = make_llm_request(llm_model, ai_func, user_req, agent_position, agent_state, agent_operation).await;
-    let res: T = serde_json::from_str(llm_res.as_str()).expect("Could not deserialize LLM response");
-    res
+    agent_operation: &str,
+    candidates: usize,
+    selector: &dyn CandidateSelector,
+    cancel: &CancellationToken
+) -> Result<String, LlmError> {
+    let req_msgs: Vec<Message> = attach_images(api_instruction_wrapper(ai_func, &user_req), images);
+    LogMessage::Info.print_message(
+        &format!("Agent: {} | State: {:?} | Performing: {} | Sampling {} candidates", agent_position, agent_state, agent_operation, candidates),
+        Color::Rgb { r: 219, g: 255, b: 51 }
+    );
+
+    let providers = route_chain(provider, agent_position, agent_operation)?;
+    let params = AcadiaConfig::global().generation.params_for(agent_operation);
+    let requests = (0..candidates.max(1) as u64).map(|index| {
+        let params = GenerationParams { seed: params.seed.map(|seed| seed + index), ..params.clone() };
+        let (providers, req_msgs) = (&providers, &req_msgs);
+        async move { send_uncached(providers, req_msgs, &params, agent_position, agent_state, agent_operation).await }
+    });
+
+    let mut answers: Vec<String> = Vec::new();
+    let mut first_error: Option<LlmError> = None;
+    for res in cancellable(cancel, async { Ok(join_all(requests).await) }).await? {
+        match res {
+            Ok(completion) => answers.push(completion.content),
+            Err(e) => {
+                LogMessage::Error.print_message(&format!("Agent: {} | Performing: {} | Candidate failed: {}", agent_position, agent_operation, e), Color::Yellow);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if answers.is_empty() {
+        return Err(first_error.unwrap_or_else(|| LlmError::MalformedResponse("no candidates were generated".to_string())));
+    }
+
+    let chosen = match selector.select(&answers).await? {
+        Some(index) => index,
+        None => {
+            LogMessage::Error.print_message(
+                &format!("Agent: {} | Performing: {} | No candidate passed the {} selector, keeping the first", agent_position, agent_operation, selector.name()),
+                Color::Yellow
+            );
+            0
+        }
+    };
+    LogMessage::Info.print_message(
+        &format!("Agent: {} | Performing: {} | Picked candidate {}/{} by {}", agent_position, agent_operation, chosen + 1, answers.len(), selector.name()),
+        Color::Rgb { r: 219, g: 255, b: 51 }
+    );
+    Ok(answers.swap_remove(chosen))
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::{ai_functions::ai_functions::convert_user_input_to_goal, function_string};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use crate::{ai_functions::ai_functions::{convert_user_input_to_goal, print_project_scope, print_site_urls}, function_string, models::general::{llm::LLMCompletion, project::ProjectScope}, providers::{mock_provider::MockProvider, provider_registry::{ProviderRegistry, GPT4O, LLAMA3}, provider_traits::LlmProvider}, utils::candidate_selection::MajorityVote};
 
     use super::*;
 
+    /// Answers every request with the same text
+    #[derive(Debug)]
+    struct FixedProvider(&'static str);
+
+    #[async_trait]
+    impl LlmProvider for FixedProvider {
+        fn name(&self) -> &str {
+            "Fixed"
+        }
+
+        fn model(&self) -> String {
+            "fixed".to_string()
+        }
+
+        async fn send_messages(&self, _messages: Vec<Message>, _params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+            Ok(LLMCompletion { content: self.0.to_string(), provider: "Fixed".to_string(), model: "fixed".to_string(), usage: None })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decoded_request_reports_raw_text() {
+        let res = make_llm_request_decoded::<Vec<String>>(
+            Arc::new(FixedProvider("Sorry, I can not list any urls")),
+            print_site_urls,
+            "Build a crypto price tracker".to_string(),
+            &[],
+            "Solutions Architect",
+            &AgentState::Working,
+            function_string!(print_site_urls),
+            &CancellationToken::new()
+        ).await;
+
+        match res {
+            Err(LlmError::Decode { raw, .. }) => assert_eq!(raw, "Sorry, I can not list any urls"),
+            other => panic!("Expected decode error, got {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decoded_request_asks_for_correction() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("print_project_scope", "The project needs CRUD but no login")
+            .respond("print_project_scope", r#"{"is_crud_required": true, "is_user_login": false, "is_external_urls_required": false}"#);
+
+        let scope = make_llm_request_decoded::<ProjectScope>(
+            mock.clone(),
+            print_project_scope,
+            "build a website that tracks todo items".to_string(),
+            &[],
+            "Solutions Architect",
+            &AgentState::Discovery,
+            function_string!(print_project_scope),
+            &CancellationToken::new()
+        ).await.unwrap();
+
+        let requests = mock.requests_for("print_project_scope");
+        assert!(scope.is_crud_required);
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].params.is_json_mode());
+        assert_eq!(requests[1].messages[2].content, "The project needs CRUD but no login");
+        mock.assert_prompt_contains("print_project_scope", "Expected schema: {\"is_crud_required\":\"bool\"");
+    }
+
+    #[tokio::test]
+    async fn test_best_of_picks_majority() {
+        let crud = r#"{"is_crud_required": true, "is_user_login": false, "is_external_urls_required": false}"#;
+        let login = r#"{"is_crud_required": true, "is_user_login": true, "is_external_urls_required": false}"#;
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("print_project_scope", login)
+            .fail("print_project_scope", LlmError::MalformedResponse("truncated".to_string()))
+            .respond("print_project_scope", crud)
+            .respond("print_project_scope", crud);
+
+        let answer = make_llm_request_best_of(
+            mock.clone(),
+            print_project_scope,
+            "build a website that tracks todo items".to_string(),
+            &[],
+            "Solutions Architect",
+            &AgentState::Discovery,
+            function_string!(print_project_scope),
+            4,
+            &MajorityVote::<ProjectScope>::new(),
+            &CancellationToken::new()
+        ).await.unwrap();
+
+        assert_eq!(answer, crud);
+        assert_eq!(mock.requests_for("print_project_scope").len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_is_not_sent() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("convert_user_input_to_goal", "build a website that tracks todo items");
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let res = make_llm_request(
+            mock.clone(),
+            convert_user_input_to_goal,
+            "I need a TODO app, cancelled before it was sent".to_string(),
+            &[],
+            "Project Manager",
+            &AgentState::Discovery,
+            function_string!(convert_user_input_to_goal),
+            &cancel
+        ).await;
+
+        assert!(matches!(res, Err(LlmError::Cancelled)));
+        assert!(mock.requests_for("convert_user_input_to_goal").is_empty());
+    }
+
+    fn wireframe() -> Vec<ImageAttachment> {
+        vec![ImageAttachment { media_type: "image/png".to_string(), data: "iVBORw0KGgo=".to_string() }]
+    }
+
+    #[tokio::test]
+    async fn test_images_attached_to_function_input() {
+        let mock = Arc::new(MockProvider::new());
+        mock.respond("convert_user_input_to_goal", "build a website that tracks todo items");
+
+        make_llm_request(
+            mock.clone(),
+            convert_user_input_to_goal,
+            "I need the TODO app from this wireframe".to_string(),
+            &wireframe(),
+            "Project Manager",
+            &AgentState::Discovery,
+            function_string!(convert_user_input_to_goal),
+            &CancellationToken::new()
+        ).await.unwrap();
+
+        let messages = &mock.requests_for("convert_user_input_to_goal")[0].messages;
+        assert!(messages[0].images.is_empty());
+        assert_eq!(messages[1].images, wireframe());
+    }
+
+    #[tokio::test]
+    async fn test_images_rejected_without_vision() {
+        let res = make_llm_request(
+            Arc::new(FixedProvider("build a website that tracks todo items")),
+            convert_user_input_to_goal,
+            "I need the TODO app from this wireframe".to_string(),
+            &wireframe(),
+            "Project Manager",
+            &AgentState::Discovery,
+            function_string!(convert_user_input_to_goal),
+            &CancellationToken::new()
+        ).await;
+
+        assert!(matches!(res, Err(LlmError::VisionUnsupported { model, .. }) if model == "fixed"));
+    }
+
     #[tokio::test]
     async fn test_llm_request() {
         let msg = Message {
             role: "user".to_string(),
-            content: "This is just a test. Can you provide the shortest response possible?".to_string()
+            content: "This is just a test. Can you provide the shortest response possible?".to_string(),
+            images: Vec::new()
         };
 
-        let res = llm_request(vec![msg], Some(LLMModel::Llama3("Llama 3 from Meta".to_string()))).await;
+        let res = llm_completion_request(vec![msg], ProviderRegistry::with_cassettes().get(LLAMA3).unwrap(), &GenerationParams::default()).await;
 
         match res {
             Ok(r) => {
-                dbg!(r);
-                ()   
+                dbg!(r.content);
             },
             Err(_) => panic!("Something went wrong with test_llm_request")
         }
@@ -196,10 +561,11 @@ mod tests {
     async fn test_gpt4() {
         let msg = Message {
             role: "user".to_string(),
-            content: "This is just a test. Can you provide the shortest response possible?".to_string()
+            content: "This is just a test. Can you provide the shortest response possible?".to_string(),
+            images: Vec::new()
         };
 
-        let res = llm_request(vec![msg], Some(LLMModel::GPT4o("GPT4o".to_string()))).await.unwrap();
+        let res = llm_completion_request(vec![msg], ProviderRegistry::with_cassettes().get(GPT4O).unwrap(), &GenerationParams::default()).await.unwrap().content;
 
         dbg!(res);
     }
@@ -207,13 +573,15 @@ mod tests {
     #[tokio::test]
     async fn test_elaborate_llm_request() {
         let res = make_llm_request(
-            LLMModel::Llama3("Llama3".to_string()),
+            ProviderRegistry::with_cassettes().get(LLAMA3).unwrap(),
             convert_user_input_to_goal,
             "Build a super simple todo app".to_string(),
+            &[],
             "Project Manager",
             &AgentState::Discovery,
-            function_string!(convert_user_input_to_goal)
-        ).await;
+            function_string!(convert_user_input_to_goal),
+            &CancellationToken::new()
+        ).await.unwrap();
 
         dbg!(res);
     }
@@ -0,0 +1,227 @@
+use crossterm::style::Color;
+use serde::Deserialize;
+use std::{future::Future, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{models::general::llm_error::LlmError, providers::provider_traits::ProviderHandle, utils::command_line::LogMessage};
+
+/// How often and how patiently a failed LLM request is retried
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 30_000
+        }
+    }
+}
+
+impl RetryPolicy {
+
+    /// Exponential backoff with jitter, between half and all of `initial * 2^(attempt - 1)`.
+    /// A `Retry-After` sent by the provider wins, up to `max_backoff_ms`.
+    pub fn backoff(&self, attempt: u32, error: &LlmError) -> Duration {
+        if let LlmError::RateLimited { retry_after: Some(wait), .. } = error {
+            return (*wait).min(Duration::from_millis(self.max_backoff_ms));
+        }
+
+        let exponential = self.initial_backoff_ms.saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let capped = exponential.min(self.max_backoff_ms);
+        Duration::from_millis(capped / 2 + jitter(capped / 2 + 1))
+    }
+}
+
+/// Pseudo random value in `0..upper`, good enough to spread retries apart
+fn jitter(upper: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos() as u64)
+        .unwrap_or_default();
+    nanos % upper.max(1)
+}
+
+/// Runs `request` until it succeeds, fails with a fatal error or runs out of attempts.
+/// Every retry is logged with the agent and the operation it was performing.
+pub async fn with_retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    agent_position: &str,
+    agent_operation: &str,
+    mut request: F
+) -> Result<T, LlmError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, LlmError>>
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt: u32 = 1;
+
+    loop {
+        match request().await {
+            Ok(res) => return Ok(res),
+            Err(e) if e.is_retryable() && attempt < max_attempts => {
+                let wait = policy.backoff(attempt, &e);
+                LogMessage::Error.print_message(
+                    &format!(
+                        "Agent: {} | Performing: {} | Attempt {}/{} failed: {} | Retrying in {:.1}s",
+                        agent_position, agent_operation, attempt, max_attempts, e, wait.as_secs_f32()
+                    ),
+                    Color::Yellow
+                );
+                tokio::time::sleep(wait).await;
+                attempt += 1;
+            },
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// Runs `request` with retries against each provider of `providers` in turn. The next provider is only
+/// tried once the current one is out of retries on a transient failure, fatal errors are returned right away.
+pub async fn with_fallback<T, F, Fut>(
+    providers: &[ProviderHandle],
+    policy: &RetryPolicy,
+    agent_position: &str,
+    agent_operation: &str,
+    mut request: F
+) -> Result<T, LlmError>
+where
+    F: FnMut(ProviderHandle) -> Fut,
+    Fut: Future<Output = Result<T, LlmError>>
+{
+    let mut providers = providers.iter().peekable();
+
+    while let Some(provider) = providers.next() {
+        match with_retry(policy, agent_position, agent_operation, || request(provider.clone())).await {
+            Err(e) if e.is_retryable() && providers.peek().is_some() => {
+                LogMessage::Error.print_message(
+                    &format!(
+                        "Agent: {} | Performing: {} | {} keeps failing: {} | Falling back to {}",
+                        agent_position, agent_operation, provider.name(), e, providers.peek().unwrap().name()
+                    ),
+                    Color::Yellow
+                );
+            },
+            res => return res
+        }
+    }
+    Err(LlmError::MissingConfig(format!("no provider configured for {} / {}", agent_position, agent_operation)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+
+    use crate::providers::{llama_provider::LlamaProvider, mock_provider::MockProvider};
+
+    use super::*;
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 2
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy { max_attempts: 10, initial_backoff_ms: 100, max_backoff_ms: 1_000 };
+        let error = LlmError::HttpStatus { status: 503, body: String::new() };
+
+        let first = policy.backoff(1, &error);
+        let third = policy.backoff(3, &error);
+        let tenth = policy.backoff(10, &error);
+
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        assert!(tenth <= Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn test_backoff_honors_retry_after() {
+        let policy = RetryPolicy::default();
+        let error = LlmError::RateLimited { retry_after: Some(Duration::from_secs(42)), body: String::new() };
+
+        assert_eq!(policy.backoff(1, &error), Duration::from_secs(30));
+        assert_eq!(RetryPolicy { max_backoff_ms: 60_000, ..policy }.backoff(1, &error), Duration::from_secs(42));
+    }
+
+    #[tokio::test]
+    async fn test_with_fallback_moves_to_next_provider() {
+        let mock = Arc::new(MockProvider::new());
+        let providers: Vec<ProviderHandle> = vec![Arc::new(LlamaProvider::new()), mock.clone()];
+
+        let res = with_fallback(&providers, &fast_policy(2), "Project Manager", "convert_user_input_to_goal", |provider| async move {
+            match provider.name() {
+                "Mock" => Ok(provider.name().to_string()),
+                _ => Err(LlmError::HttpStatus { status: 503, body: "overloaded".to_string() })
+            }
+        }).await;
+
+        assert_eq!(res.unwrap(), "Mock");
+    }
+
+    #[tokio::test]
+    async fn test_with_fallback_stops_on_fatal_error() {
+        let providers: Vec<ProviderHandle> = vec![Arc::new(LlamaProvider::new()), Arc::new(MockProvider::new())];
+        let attempts = AtomicU32::new(0);
+
+        let res: Result<String, LlmError> = with_fallback(&providers, &fast_policy(2), "Backend Developer", "print_backend_webserver_code", |_| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(LlmError::HttpStatus { status: 401, body: "invalid api key".to_string() })
+        }).await;
+
+        assert!(matches!(res, Err(LlmError::HttpStatus { status: 401, .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_retry_recovers_from_transient_errors() {
+        let attempts = AtomicU32::new(0);
+
+        let res = with_retry(&fast_policy(3), "Backend Developer", "print_backend_webserver_code", || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(LlmError::HttpStatus { status: 502, body: "bad gateway".to_string() }),
+                _ => Ok("fn main() {}".to_string())
+            }
+        }).await;
+
+        assert_eq!(res.unwrap(), "fn main() {}");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_with_retry_stops_on_fatal_error() {
+        let attempts = AtomicU32::new(0);
+
+        let res: Result<String, LlmError> = with_retry(&fast_policy(3), "Project Manager", "convert_user_input_to_goal", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(LlmError::HttpStatus { status: 401, body: "invalid api key".to_string() })
+        }).await;
+
+        assert!(matches!(res, Err(LlmError::HttpStatus { status: 401, .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_retry_gives_up_after_max_attempts() {
+        let attempts = AtomicU32::new(0);
+
+        let res: Result<String, LlmError> = with_retry(&fast_policy(3), "Project Manager", "convert_user_input_to_goal", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(LlmError::Transport("connection refused".to_string()))
+        }).await;
+
+        assert!(matches!(res, Err(LlmError::Transport(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,5 +1,18 @@
+pub mod audit_log;
+pub mod cancellation;
+pub mod candidate_selection;
 pub mod code_generation;
 pub mod command_line;
 pub mod files_io;
 pub mod helper;
-pub mod llm_requests;
\ No newline at end of file
+pub mod http_client;
+pub mod json_repair;
+pub mod json_schema;
+pub mod llm_requests;
+pub mod llm_retry;
+pub mod preflight;
+pub mod rate_limiter;
+pub mod response_cache;
+pub mod token_counting;
+pub mod tool_calling;
+pub mod usage_tracking;
@@ -0,0 +1,137 @@
+use crossterm::style::Color;
+
+use crate::{models::general::llm_error::LlmError, providers::{model_router::RoutingConfig, provider_registry::ProviderRegistry, provider_traits::ProviderHandle}, utils::command_line::LogMessage};
+
+/// Outcome of checking one provider the run will use
+#[derive(Debug)]
+pub struct PreflightResult {
+    pub provider: String,
+    pub model: Option<String>,
+    /// Agents and routing entries that send requests to the provider
+    pub used_by: Vec<String>,
+    pub result: Result<(), LlmError>
+}
+
+/// Providers of the agents plus every provider the routing table points to, each listed once
+fn run_providers(agent_providers: &[(String, ProviderHandle)], routing: &RoutingConfig, registry: &ProviderRegistry) -> Vec<(String, Option<ProviderHandle>, Vec<String>)> {
+    let mut providers: Vec<(String, Option<ProviderHandle>, Vec<String>)> = Vec::new();
+    let referenced = agent_providers.iter()
+        .map(|(position, provider)| (provider.name().to_string(), Some(provider.clone()), position.clone()))
+        .chain(routing.referenced_providers().into_iter().map(|(name, used_by)| (name.clone(), registry.get(&name), used_by)));
+
+    for (name, provider, used_by) in referenced {
+        match providers.iter_mut().find(|(known, _, _)| *known == name) {
+            Some((_, _, users)) => users.push(used_by),
+            None => providers.push((name, provider, vec![used_by]))
+        }
+    }
+    providers
+}
+
+/// Checks the configuration of every provider the run will use and, with `ping`, that each endpoint answers
+pub async fn check_providers(agent_providers: &[(String, ProviderHandle)], routing: &RoutingConfig, registry: &ProviderRegistry, ping: bool) -> Vec<PreflightResult> {
+    let mut results = Vec::new();
+    for (name, provider, used_by) in run_providers(agent_providers, routing, registry) {
+        let Some(provider) = provider else {
+            results.push(PreflightResult {
+                result: Err(LlmError::MissingConfig(format!("{} is not a registered provider, check the routing section of acadia.json", name))),
+                provider: name,
+                model: None,
+                used_by
+            });
+            continue;
+        };
+
+        let mut result = provider.check_config();
+        if ping && result.is_ok() {
+            result = provider.ping().await;
+        }
+        results.push(PreflightResult { provider: name, model: Some(provider.model()), used_by, result });
+    }
+    results
+}
+
+/// Prints one line per provider and fails when any of them is misconfigured, before any tokens are spent
+pub async fn run_preflight(agent_providers: &[(String, ProviderHandle)], routing: &RoutingConfig, registry: &ProviderRegistry, ping: bool) -> Result<(), LlmError> {
+    LogMessage::Info.print_message(
+        &format!("Preflight: checking {} of the run's providers", if ping { "and pinging" } else { "the configuration" }),
+        Color::Rgb { r: 19, g: 214, b: 185 }
+    );
+
+    let results = check_providers(agent_providers, routing, registry, ping).await;
+    let failed = results.iter().filter(|res| res.result.is_err()).count();
+    for res in &results {
+        let label = match &res.model {
+            Some(model) => format!("{} ({})", res.provider, model),
+            None => res.provider.clone()
+        };
+        match &res.result {
+            Ok(()) => LogMessage::Info.print_message(&format!("  ok    {} | used by {}", label, res.used_by.join(", ")), Color::Green),
+            Err(e) => LogMessage::Error.print_message(&format!("  error {} | used by {} | {}", label, res.used_by.join(", "), e), Color::Red)
+        }
+    }
+
+    if failed > 0 {
+        return Err(LlmError::MissingConfig(format!(
+            "preflight found {} misconfigured provider(s), fix the .env file or acadia.json and run again", failed
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    use crate::{models::general::{generation::GenerationParams, llm::{LLMCompletion, Message}}, providers::{mock_provider::MockProvider, provider_traits::LlmProvider}};
+
+    use super::*;
+
+    /// Provider whose credentials are missing
+    #[derive(Debug)]
+    struct UnconfiguredProvider;
+
+    #[async_trait]
+    impl LlmProvider for UnconfiguredProvider {
+        fn name(&self) -> &str {
+            "Unconfigured"
+        }
+
+        fn model(&self) -> String {
+            "unconfigured".to_string()
+        }
+
+        fn check_config(&self) -> Result<(), LlmError> {
+            Err(LlmError::MissingConfig("UNCONFIGURED_KEY is not set in the .env file".to_string()))
+        }
+
+        async fn send_messages(&self, _messages: Vec<Message>, _params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+            unreachable!("preflight never sends messages")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preflight_reports_every_provider() {
+        let mock: ProviderHandle = Arc::new(MockProvider::new());
+        let mut registry = ProviderRegistry::new();
+        registry.register("Unconfigured", Arc::new(UnconfiguredProvider));
+        let routing: RoutingConfig = serde_json::from_str(r#"{
+            "functions": { "print_project_scope": "Unconfigured" },
+            "fallbacks": { "agents": { "Backend Developer": ["Missing"] } }
+        }"#).unwrap();
+        let agents = vec![("Project Manager".to_string(), mock.clone()), ("Backend Developer".to_string(), mock)];
+
+        let results = check_providers(&agents, &routing, &registry, true).await;
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].used_by, vec!["Project Manager", "Backend Developer"]);
+        assert!(results[0].result.is_ok());
+        assert_eq!(results[1].provider, "Missing");
+        assert!(results[1].result.is_err());
+        assert_eq!(results[2].used_by, vec!["route of print_project_scope"]);
+        assert!(matches!(&results[2].result, Err(LlmError::MissingConfig(msg)) if msg.contains("UNCONFIGURED_KEY")));
+        assert!(run_preflight(&agents, &routing, &registry, false).await.is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use crossterm::style::Color;
+use serde::Deserialize;
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex, OnceLock}, time::{Duration, Instant}};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{models::general::{config::AcadiaConfig, llm::{Message, TokenUsage}}, utils::{command_line::LogMessage, token_counting::estimate_message_tokens}};
+
+/// Limits are counted over a sliding window of one minute
+const WINDOW: Duration = Duration::from_secs(60);
+
+static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RateLimiter>>>> = OnceLock::new();
+
+/// Limits for one provider, unset limits are not enforced
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+    pub max_in_flight: Option<usize>
+}
+
+/// `rate_limits` section of `acadia.json`, keyed by provider registry name
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct RateLimitConfig {
+    providers: HashMap<String, RateLimit>
+}
+
+impl RateLimitConfig {
+    pub fn limit_for(&self, provider: &str) -> Option<&RateLimit> {
+        self.providers.get(provider)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    requests: VecDeque<Instant>,
+    tokens: VecDeque<(Instant, u64)>
+}
+
+impl Window {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while self.requests.front().is_some_and(|sent| now.duration_since(*sent) >= window) {
+            self.requests.pop_front();
+        }
+        while self.tokens.front().is_some_and(|(sent, _)| now.duration_since(*sent) >= window) {
+            self.tokens.pop_front();
+        }
+    }
+}
+
+/// Requests and tokens per minute plus in-flight requests of one provider, shared by every agent
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    window: Duration,
+    sent: Mutex<Window>,
+    in_flight: Option<Arc<Semaphore>>
+}
+
+/// Held for the duration of a request, releases its in-flight slot when dropped
+#[derive(Debug)]
+pub struct RateLimitPermit {
+    limiter: Arc<RateLimiter>,
+    reserved_tokens: u64,
+    _in_flight: Option<OwnedSemaphorePermit>
+}
+
+impl RateLimitPermit {
+    /// Counts the tokens the provider reported beyond the estimate reserved for the request
+    pub fn settle(&self, usage: Option<TokenUsage>) {
+        let Some(usage) = usage else { return };
+        let extra_tokens = usage.total().saturating_sub(self.reserved_tokens);
+        if extra_tokens > 0 {
+            self.limiter.sent.lock().unwrap_or_else(|e| e.into_inner()).tokens.push_back((Instant::now(), extra_tokens));
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit, window: Duration) -> Self {
+        RateLimiter {
+            in_flight: limit.max_in_flight.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            limit,
+            window,
+            sent: Mutex::new(Window::default())
+        }
+    }
+
+    /// How long to wait before a request of `tokens` fits in the window, and which limit it waits on
+    fn reserve(&self, tokens: u64) -> Option<(Duration, &'static str)> {
+        let now = Instant::now();
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        sent.prune(now, self.window);
+
+        if let Some(rpm) = self.limit.requests_per_minute {
+            if sent.requests.len() >= rpm.max(1) as usize {
+                return Some((self.window - now.duration_since(sent.requests[0]), "requests per minute"));
+            }
+        }
+        if let Some(tpm) = self.limit.tokens_per_minute {
+            let used: u64 = sent.tokens.iter().map(|(_, tokens)| tokens).sum();
+            // A single request larger than the whole budget still goes out once the window is empty
+            if used + tokens > tpm && !sent.tokens.is_empty() {
+                return Some((self.window - now.duration_since(sent.tokens[0].0), "tokens per minute"));
+            }
+        }
+
+        sent.requests.push_back(now);
+        sent.tokens.push_back((now, tokens));
+        None
+    }
+
+    /// Waits until the request fits every limit. Returns the permit and the time spent waiting per limit.
+    pub async fn acquire(self: &Arc<Self>, tokens: u64) -> (RateLimitPermit, Vec<(&'static str, Duration)>) {
+        let mut waits: Vec<(&'static str, Duration)> = Vec::new();
+
+        let in_flight = match &self.in_flight {
+            Some(semaphore) => {
+                let started = Instant::now();
+                let permit = semaphore.clone().acquire_owned().await.expect("rate limiter semaphore is never closed");
+                if started.elapsed() >= Duration::from_millis(1) {
+                    waits.push(("max in flight", started.elapsed()));
+                }
+                Some(permit)
+            },
+            None => None
+        };
+
+        while let Some((wait, limit)) = self.reserve(tokens) {
+            waits.push((limit, wait));
+            tokio::time::sleep(wait).await;
+        }
+
+        (RateLimitPermit { limiter: self.clone(), reserved_tokens: tokens, _in_flight: in_flight }, waits)
+    }
+}
+
+/// Waits for the provider's rate limits, logging any throttling. None when the provider has no limits configured.
+pub async fn throttle(provider: &str, model: &str, messages: &[Message], agent_position: &str, agent_operation: &str) -> Option<RateLimitPermit> {
+    let limiter = {
+        let mut limiters = LIMITERS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        match limiters.get(provider) {
+            Some(limiter) => limiter.clone(),
+            None => {
+                let limit = AcadiaConfig::global().rate_limits.limit_for(provider)?.clone();
+                let limiter = Arc::new(RateLimiter::new(limit, WINDOW));
+                limiters.insert(provider.to_string(), limiter.clone());
+                limiter
+            }
+        }
+    };
+
+    let (permit, waits) = limiter.acquire(estimate_message_tokens(model, messages)).await;
+    for (limit, wait) in waits {
+        LogMessage::Error.print_message(
+            &format!("Agent: {} | Performing: {} | Throttled {:.1}s by the {} {} limit", agent_position, agent_operation, wait.as_secs_f32(), provider, limit),
+            Color::Yellow
+        );
+    }
+    Some(permit)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_per_minute_waits_for_window() {
+        let limit = RateLimit { requests_per_minute: Some(2), ..RateLimit::default() };
+        let limiter = Arc::new(RateLimiter::new(limit, Duration::from_millis(100)));
+
+        let (_, first_waits) = limiter.acquire(10).await;
+        let (_, second_waits) = limiter.acquire(10).await;
+        let (_, third_waits) = limiter.acquire(10).await;
+
+        assert!(first_waits.is_empty() && second_waits.is_empty());
+        assert_eq!(third_waits[0].0, "requests per minute");
+    }
+
+    #[tokio::test]
+    async fn test_tokens_per_minute_counts_reported_usage() {
+        let limit = RateLimit { tokens_per_minute: Some(100), ..RateLimit::default() };
+        let limiter = Arc::new(RateLimiter::new(limit, Duration::from_millis(100)));
+
+        let (permit, _) = limiter.acquire(10).await;
+        permit.settle(Some(TokenUsage { prompt_tokens: 10, completion_tokens: 80 }));
+        let (_, waits) = limiter.acquire(20).await;
+
+        assert_eq!(waits[0].0, "tokens per minute");
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let limit = RateLimit { max_in_flight: Some(1), ..RateLimit::default() };
+        let limiter = Arc::new(RateLimiter::new(limit, WINDOW));
+
+        let (permit, _) = limiter.acquire(10).await;
+        assert!(tokio::time::timeout(Duration::from_millis(20), limiter.acquire(10)).await.is_err());
+
+        drop(permit);
+        assert!(tokio::time::timeout(Duration::from_millis(20), limiter.acquire(10)).await.is_ok());
+    }
+}
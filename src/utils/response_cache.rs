@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, io, path::PathBuf, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::models::general::{generation::GenerationParams, llm::{LLMCompletion, Message}};
+
+/// `cache` section of `acadia.json`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub dir: String,
+    pub ttl_secs: u64
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            dir: ".acadia_cache".to_string(),
+            ttl_secs: 24 * 60 * 60
+        }
+    }
+}
+
+/// Everything that changes the answer of a request, hashed into the cache key
+#[derive(Debug, Serialize)]
+struct CacheKey<'a> {
+    provider: &'a str,
+    model: &'a str,
+    messages: &'a [Message],
+    params: &'a GenerationParams
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResponse {
+    /// Unix timestamp in seconds
+    created_at: u64,
+    completion: LLMCompletion
+}
+
+/// Content addressed store of LLM answers, one JSON file per request
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default()
+}
+
+impl ResponseCache {
+    pub fn new(dir: &str, ttl: Duration) -> Self {
+        ResponseCache {
+            dir: PathBuf::from(dir),
+            ttl
+        }
+    }
+
+    /// None when caching is turned off
+    pub fn from_config(config: &CacheConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(ResponseCache::new(&config.dir, Duration::from_secs(config.ttl_secs)))
+    }
+
+    /// SHA-256 of the provider, model, messages and generation parameters
+    pub fn key(provider: &str, model: &str, messages: &[Message], params: &GenerationParams) -> String {
+        let key = CacheKey { provider, model, messages, params };
+        let serialized = serde_json::to_vec(&key).unwrap_or_default();
+
+        Sha256::digest(&serialized)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Cached answer for `key`, expired or unreadable entries count as a miss
+    pub fn get(&self, key: &str) -> Option<LLMCompletion> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        let cached: CachedResponse = serde_json::from_str(&contents).ok()?;
+
+        if unix_now().saturating_sub(cached.created_at) > self.ttl.as_secs() {
+            return None;
+        }
+        Some(cached.completion)
+    }
+
+    pub fn put(&self, key: &str, completion: &LLMCompletion) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let cached = CachedResponse {
+            created_at: unix_now(),
+            completion: completion.clone()
+        };
+        fs::write(self.path(key), serde_json::to_string_pretty(&cached)?)
+    }
+
+    /// Removes every cached answer, returns how many were removed
+    pub fn clear(&self) -> io::Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn test_cache(name: &str, ttl: Duration) -> ResponseCache {
+        let dir = std::env::temp_dir().join(format!("acadia_test_cache_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        ResponseCache::new(dir.to_str().unwrap(), ttl)
+    }
+
+    fn completion(content: &str) -> LLMCompletion {
+        LLMCompletion {
+            content: content.to_string(),
+            provider: "GPT-4o".to_string(),
+            model: "gpt-4o".to_string(),
+            usage: None
+        }
+    }
+
+    #[test]
+    fn test_key_depends_on_model_messages_and_params() {
+        let messages = vec![Message { role: "user".to_string(), content: "Build a todo app".to_string(), images: Vec::new() }];
+        let other_messages = vec![Message { role: "user".to_string(), content: "Build a blog".to_string(), images: Vec::new() }];
+
+        let params = GenerationParams::default();
+        let low_temperature = GenerationParams { temperature: Some(0.0), ..GenerationParams::default() };
+
+        let key = ResponseCache::key("GPT-4o", "gpt-4o", &messages, &params);
+
+        assert_eq!(key, ResponseCache::key("GPT-4o", "gpt-4o", &messages, &params));
+        assert_ne!(key, ResponseCache::key("GPT-4o", "gpt-4o-mini", &messages, &params));
+        assert_ne!(key, ResponseCache::key("GPT-4o", "gpt-4o", &other_messages, &params));
+        assert_ne!(key, ResponseCache::key("GPT-4o", "gpt-4o", &messages, &low_temperature));
+        assert_eq!(key.len(), 64);
+    }
+
+    #[test]
+    fn test_put_get_and_clear() {
+        let cache = test_cache("put_get", Duration::from_secs(60));
+
+        assert!(cache.get("abc").is_none());
+        cache.put("abc", &completion("build a website that tracks todo items")).unwrap();
+
+        assert_eq!(cache.get("abc").unwrap().content, "build a website that tracks todo items");
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.get("abc").is_none());
+    }
+
+    #[test]
+    fn test_expired_entry_is_a_miss() {
+        let cache = test_cache("expired", Duration::from_secs(60));
+        let stale = CachedResponse { created_at: unix_now() - 120, completion: completion("stale") };
+        fs::create_dir_all(&cache.dir).unwrap();
+        fs::write(cache.path("old"), serde_json::to_string(&stale).unwrap()).unwrap();
+
+        assert!(cache.get("old").is_none());
+    }
+}
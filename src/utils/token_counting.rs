@@ -0,0 +1,135 @@
+use crossterm::style::Color;
+
+use crate::{models::general::{config::AcadiaConfig, generation::GenerationParams, llm::Message, llm_error::LlmError, model_capabilities::ModelCapability}, utils::command_line::LogMessage};
+
+/// Tokens every message costs on top of its content (role, separators)
+const MESSAGE_OVERHEAD_TOKENS: u64 = 4;
+
+/// Tokens an attached image is counted as, about what a high detail 1024x1024 image costs with GPT-4o and Claude
+const IMAGE_TOKENS: u64 = 1_000;
+
+/// Smallest answer worth sending a request for once the prompt has taken its share of the window
+const MIN_OUTPUT_TOKENS: u32 = 256;
+
+/// Characters per token when the model is not in the capability table
+const DEFAULT_CHARS_PER_TOKEN: f64 = 4.0;
+
+fn chars_per_token(model: &str) -> f64 {
+    AcadiaConfig::global().models.capability(model).map_or(DEFAULT_CHARS_PER_TOKEN, |capability| capability.chars_per_token)
+}
+
+fn estimate_with(chars_per_token: f64, text: &str) -> u64 {
+    (text.chars().count() as f64 / chars_per_token).ceil() as u64
+}
+
+/// Estimated number of tokens `text` takes for `model`
+pub fn estimate_tokens(model: &str, text: &str) -> u64 {
+    estimate_with(chars_per_token(model), text)
+}
+
+/// Estimated prompt size of a whole conversation for `model`
+pub fn estimate_message_tokens(model: &str, messages: &[Message]) -> u64 {
+    messages
+        .iter()
+        .map(|message| estimate_tokens(model, &message.content) + message.images.len() as u64 * IMAGE_TOKENS + MESSAGE_OVERHEAD_TOKENS)
+        .sum()
+}
+
+/// Keeps the start of `text` within `max_tokens` for `model`. Returns the text and whether anything was cut.
+pub fn truncate_to_tokens(model: &str, text: &str, max_tokens: u64) -> (String, bool) {
+    let max_chars = (max_tokens as f64 * chars_per_token(model)) as usize;
+    match text.char_indices().nth(max_chars) {
+        Some((cut, _)) => (text[..cut].to_string(), true),
+        None => (text.to_string(), false)
+    }
+}
+
+/// Output tokens a request can ask for, so a prompt plus its answer stays within the window
+fn output_reserve(capability: &ModelCapability, params: &GenerationParams) -> u32 {
+    params.max_tokens.unwrap_or(MIN_OUTPUT_TOKENS).min(capability.max_output_tokens)
+}
+
+/// Prompt tokens left for `model` once the answer of a request with `params` is reserved, None for unknown models
+pub fn prompt_budget(model: &str, params: &GenerationParams) -> Option<u64> {
+    let capability = AcadiaConfig::global().models.capability(model)?;
+    Some(capability.context_tokens.saturating_sub(output_reserve(&capability, params) as u64))
+}
+
+/// Checks the request fits the model's context window before it is sent.
+/// `max_tokens` is lowered to what the model and the remaining window allow, a prompt that leaves
+/// no room for an answer is an error. Unknown models are sent as they are.
+pub fn fit_context_window(model: &str, messages: &[Message], params: &GenerationParams) -> Result<GenerationParams, LlmError> {
+    let Some(capability) = AcadiaConfig::global().models.capability(model) else {
+        return Ok(params.clone());
+    };
+    let prompt_tokens = estimate_message_tokens(model, messages);
+    let room = capability.context_tokens.saturating_sub(prompt_tokens);
+
+    if room < MIN_OUTPUT_TOKENS as u64 {
+        return Err(LlmError::ContextLengthExceeded { model: model.to_string(), prompt_tokens, context_tokens: capability.context_tokens });
+    }
+
+    let mut params = params.clone();
+    if let Some(max_tokens) = params.max_tokens {
+        let allowed = (max_tokens as u64).min(room).min(capability.max_output_tokens as u64) as u32;
+        if allowed < max_tokens {
+            LogMessage::Error.print_message(
+                &format!("{} allows {} output tokens for this prompt, lowering max_tokens from {}", model, allowed, max_tokens),
+                Color::Yellow
+            );
+            params.max_tokens = Some(allowed);
+        }
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::models::general::llm::ImageAttachment;
+
+    use super::*;
+
+    fn user_message(content: String) -> Vec<Message> {
+        vec![Message { role: "user".to_string(), content, images: Vec::new() }]
+    }
+
+    #[test]
+    fn test_estimate_depends_on_model() {
+        let text = "a".repeat(700);
+
+        assert_eq!(estimate_tokens("gpt-4o", &text), 175);
+        assert_eq!(estimate_tokens("claude-3-5-sonnet-latest", &text), 200);
+        assert_eq!(estimate_message_tokens("gpt-4o", &user_message(text)), 179);
+    }
+
+    #[test]
+    fn test_estimate_counts_images() {
+        let mockup = ImageAttachment { media_type: "image/png".to_string(), data: "iVBORw0KGgo=".to_string() };
+        let messages = vec![Message { role: "user".to_string(), content: String::new(), images: vec![mockup.clone(), mockup] }];
+
+        assert_eq!(estimate_message_tokens("gpt-4o", &messages), 2 * IMAGE_TOKENS + MESSAGE_OVERHEAD_TOKENS);
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let (kept, truncated) = truncate_to_tokens("gpt-4o", "fn main() { println!(\"hello\"); }", 2);
+        let (whole, not_truncated) = truncate_to_tokens("gpt-4o", "fn main() {}", 100);
+
+        assert_eq!((kept.as_str(), truncated), ("fn main(", true));
+        assert_eq!((whole.as_str(), not_truncated), ("fn main() {}", false));
+    }
+
+    #[test]
+    fn test_fit_context_window() {
+        let params = GenerationParams { max_tokens: Some(4_000), ..GenerationParams::default() };
+        let small_prompt = user_message("Build a todo app".to_string());
+        let large_prompt = user_message("a".repeat(8_192 * 4));
+        let medium_prompt = user_message("a".repeat(6_000 * 4));
+
+        assert_eq!(fit_context_window("llama3", &small_prompt, &params).unwrap().max_tokens, Some(2_048));
+        assert!(fit_context_window("llama3", &medium_prompt, &params).unwrap().max_tokens.unwrap() < 2_048);
+        assert!(matches!(fit_context_window("llama3", &large_prompt, &params), Err(LlmError::ContextLengthExceeded { .. })));
+        assert_eq!(fit_context_window("mystery-model", &large_prompt, &params).unwrap(), params);
+    }
+}
@@ -0,0 +1,312 @@
+use crossterm::style::Color;
+use serde_json::{json, Value};
+use std::{future::Future, path::Path, pin::Pin, sync::Arc, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::{agents::base::agent_traits::AgentState, models::general::{config::AcadiaConfig, llm::Message, llm_error::LlmError, tools::{ToolCall, ToolDefinition, ToolTurn}}, providers::{model_router::route_chain, provider_traits::ProviderHandle}, utils::{audit_log::{audit_exchange, AuditRecord}, cancellation::{cancellable, check_cancelled}, command_line::LogMessage, files_io::try_read_code_template_contents, helper::{cargo_build, check_url_status_code, EXTERNAL_URL_TIMEOUT}, http_client::shared_client, llm_requests::require_vision, llm_retry::with_fallback, rate_limiter::throttle, token_counting::fit_context_window, usage_tracking::{record_usage, reserve_budget}}};
+
+/// Rounds of tool calls a conversation may go through before the model has to answer
+pub const MAX_TOOL_ROUNDS: usize = 8;
+
+/// Names of the built in tools
+pub const CHECK_URL: &str = "check_url";
+pub const READ_TEMPLATE: &str = "read_template";
+pub const RUN_BUILD: &str = "run_build";
+
+/// Result of a tool handler, errors are sent back to the model as the tool result
+pub type ToolFuture = Pin<Box<dyn Future<Output = Result<String, String>> + Send>>;
+
+/// Runs a tool with the arguments the model called it with
+pub type ToolHandler = Arc<dyn Fn(Value) -> ToolFuture + Send + Sync>;
+
+/// Tools offered to the model along with the Rust handlers that run them
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<(ToolDefinition, ToolHandler)>
+}
+
+impl std::fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.tools.iter().map(|(definition, _)| &definition.name)).finish()
+    }
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        ToolRegistry::default()
+    }
+
+    /// Adds a tool, `parameters` is the JSON Schema of the arguments object. A tool with the same name is replaced.
+    pub fn register<F, Fut>(&mut self, name: &str, description: &str, parameters: Value, handler: F) -> &mut Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static
+    {
+        self.tools.retain(|(definition, _)| definition.name != name);
+        let definition = ToolDefinition { name: name.to_string(), description: description.to_string(), parameters };
+        let handler: ToolHandler = Arc::new(move |arguments| Box::pin(handler(arguments)));
+        self.tools.push((definition, handler));
+        self
+    }
+
+    /// Keeps only the tools named in `names`, so an agent is offered just the tools its task needs
+    pub fn select(mut self, names: &[&str]) -> Self {
+        self.tools.retain(|(definition, _)| names.contains(&definition.name.as_str()));
+        self
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|(definition, _)| definition.clone()).collect()
+    }
+
+    /// Runs the handler of `call`. Unknown tools and handler errors become the result so the model can react to them.
+    pub async fn call(&self, call: &ToolCall) -> String {
+        let Some((_, handler)) = self.tools.iter().find(|(definition, _)| definition.name == call.name) else {
+            return format!("Error: there is no tool named {}", call.name);
+        };
+        match handler(call.arguments.clone()).await {
+            Ok(result) => result,
+            Err(e) => format!("Error: {}", e)
+        }
+    }
+}
+
+/// Reads a string argument of a tool call
+fn string_argument(arguments: &Value, name: &str) -> Result<String, String> {
+    arguments[name].as_str().map(str::to_string).ok_or_else(|| format!("missing string argument `{}`", name))
+}
+
+/// Tools agents can use without any setup: checking urls, reading code templates and building the project in `project_dir`
+pub fn builtin_tools(project_dir: &str) -> ToolRegistry {
+    let project_dir = project_dir.to_string();
+    let mut tools = ToolRegistry::new();
+    tools
+        .register(
+            CHECK_URL,
+            "Sends a GET request to a url and returns the HTTP status code",
+            json!({
+                "type": "object",
+                "properties": { "url": { "type": "string", "description": "Absolute url to request" } },
+                "required": ["url"]
+            }),
+            |arguments| async move {
+                let url = string_argument(&arguments, "url")?;
+                let client = shared_client().map_err(|e| e.to_string())?;
+                check_url_status_code(&client, &url, EXTERNAL_URL_TIMEOUT).await.map(|status| status.to_string()).map_err(|e| e.to_string())
+            }
+        )
+        .register(
+            READ_TEMPLATE,
+            "Returns the code template the backend is built from",
+            json!({
+                "type": "object",
+                "properties": { "language": { "type": "string", "description": "Language of the template, for example Rust" } },
+                "required": ["language"]
+            }),
+            |arguments| async move {
+                let language = string_argument(&arguments, "language")?;
+                try_read_code_template_contents(&language).map_err(|e| e.to_string())
+            }
+        )
+        .register(
+            RUN_BUILD,
+            "Runs cargo build on the generated backend code and returns the compiler errors, if any",
+            json!({ "type": "object", "properties": {} }),
+            move |_| {
+                let project_dir = project_dir.clone();
+                async move {
+                    tokio::task::spawn_blocking(move || cargo_build(Path::new(&project_dir), None))
+                        .await
+                        .map_err(|e| format!("build panicked: {}", e))?
+                        .map(|()| "Build succeeded".to_string())
+                }
+            }
+        );
+    tools
+}
+
+/// Sends the conversation with the registered tools and runs every tool the model calls, until the model
+/// answers without calling any. Each round goes through the budget check, rate limits, retries and fallbacks
+/// like any other request and records its usage. Cancelling the run aborts the round in flight, no further tools are run.
+pub async fn make_llm_request_with_tools(
+    provider: ProviderHandle,
+    tools: &ToolRegistry,
+    messages: Vec<Message>,
+    agent_position: &str,
+    agent_state: &AgentState,
+    agent_operation: &str,
+    cancel: &CancellationToken
+) -> Result<String, LlmError> {
+    let providers = route_chain(provider, agent_position, agent_operation)?;
+    let params = AcadiaConfig::global().generation.params_for(agent_operation);
+    let retry_policy = &AcadiaConfig::global().retry;
+    let definitions = tools.definitions();
+    let mut conversation: Vec<ToolTurn> = messages.into_iter().map(ToolTurn::from).collect();
+
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let messages: Vec<Message> = conversation.iter().map(ToolTurn::to_message).collect();
+        let _reservation = reserve_budget(&providers[0].model(), &messages, &params)?;
+        let (conversation_ref, messages, definitions, params) = (&conversation, &messages, &definitions, &params);
+        let answer = cancellable(cancel, with_fallback(&providers, retry_policy, agent_position, agent_operation, |provider| async move {
+            let model = provider.model();
+            require_vision(&provider, messages)?;
+            let params = fit_context_window(&model, messages, params)?;
+            let permit = throttle(provider.name(), &model, messages, agent_position, agent_operation).await;
+            let started = Instant::now();
+            let res = provider.send_with_tools(conversation_ref.clone(), definitions, &params).await;
+            let outcome = res.as_ref().map(|answer| &answer.completion);
+            audit_exchange(&AuditRecord::new(agent_position, agent_state, agent_operation, (provider.name(), &model), conversation_ref, outcome, started.elapsed()));
+            let answer = res?;
+            if let Some(permit) = permit {
+                permit.settle(answer.completion.usage);
+            }
+            Ok(answer)
+        })).await?;
+        record_usage(&answer.completion, agent_position, agent_operation);
+
+        if answer.tool_calls.is_empty() {
+            return Ok(answer.completion.content);
+        }
+
+        conversation.push(ToolTurn::ToolCalls { content: answer.completion.content, calls: answer.tool_calls.clone() });
+        for call in answer.tool_calls {
+            check_cancelled(cancel)?;
+            LogMessage::Info.print_message(
+                &format!("Agent: {} | Performing: {} | Calling tool {} with {}", agent_position, agent_operation, call.name, call.arguments),
+                Color::Rgb { r: 219, g: 255, b: 51 }
+            );
+            let content = tools.call(&call).await;
+            conversation.push(ToolTurn::ToolResult { call_id: call.id, name: call.name, content });
+        }
+    }
+
+    Err(LlmError::ToolCalling(format!("{} still called tools after {} rounds", providers[0].name(), MAX_TOOL_ROUNDS)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    use crate::{agents::base::agent_traits::AgentState, models::general::{generation::GenerationParams, llm::LLMCompletion, tools::ToolCompletion}, providers::{llama_provider::LlamaProvider, provider_traits::LlmProvider}};
+
+    use super::*;
+
+    /// Calls `add` until it has seen `rounds` tool results, then answers with the last result
+    #[derive(Debug)]
+    struct AddingProvider {
+        rounds: usize,
+        conversations: Mutex<Vec<Vec<ToolTurn>>>
+    }
+
+    #[async_trait]
+    impl LlmProvider for AddingProvider {
+        fn name(&self) -> &str {
+            "Adding"
+        }
+
+        fn model(&self) -> String {
+            "adding".to_string()
+        }
+
+        async fn send_messages(&self, _messages: Vec<Message>, _params: &GenerationParams) -> Result<LLMCompletion, LlmError> {
+            unreachable!("only tool requests are sent")
+        }
+
+        async fn send_with_tools(&self, conversation: Vec<ToolTurn>, _tools: &[ToolDefinition], _params: &GenerationParams) -> Result<ToolCompletion, LlmError> {
+            let results: Vec<String> = conversation.iter().filter_map(|turn| match turn {
+                ToolTurn::ToolResult { content, .. } => Some(content.clone()),
+                _ => None
+            }).collect();
+            self.conversations.lock().unwrap().push(conversation);
+
+            let tool_calls = if results.len() < self.rounds {
+                vec![ToolCall { id: format!("call_{}", results.len()), name: "add".to_string(), arguments: json!({ "a": results.len(), "b": 1 }) }]
+            } else {
+                Vec::new()
+            };
+            Ok(ToolCompletion {
+                completion: LLMCompletion { content: results.last().cloned().unwrap_or_default(), provider: "Adding".to_string(), model: "adding".to_string(), usage: None },
+                tool_calls
+            })
+        }
+    }
+
+    fn adding_tools() -> ToolRegistry {
+        let mut tools = ToolRegistry::new();
+        tools.register("add", "Adds two numbers", json!({ "type": "object" }), |arguments| async move {
+            match (arguments["a"].as_u64(), arguments["b"].as_u64()) {
+                (Some(a), Some(b)) => Ok((a + b).to_string()),
+                _ => Err("a and b must be numbers".to_string())
+            }
+        });
+        tools
+    }
+
+    fn user_message() -> Vec<Message> {
+        vec![Message { role: "user".to_string(), content: "Count to three".to_string(), images: Vec::new() }]
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_runs_handlers_until_answer() {
+        let provider = Arc::new(AddingProvider { rounds: 3, conversations: Mutex::new(Vec::new()) });
+
+        let answer = make_llm_request_with_tools(provider.clone(), &adding_tools(), user_message(), "Tester", &AgentState::Working, "count", &CancellationToken::new()).await.unwrap();
+        let conversations = provider.conversations.lock().unwrap();
+
+        assert_eq!(answer, "3");
+        assert_eq!(conversations.len(), 4);
+        assert_eq!(conversations[1][2], ToolTurn::ToolResult { call_id: "call_0".to_string(), name: "add".to_string(), content: "1".to_string() });
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_stops_after_max_rounds() {
+        let provider = Arc::new(AddingProvider { rounds: usize::MAX, conversations: Mutex::new(Vec::new()) });
+
+        let res = make_llm_request_with_tools(provider.clone(), &adding_tools(), user_message(), "Tester", &AgentState::Working, "count", &CancellationToken::new()).await;
+
+        assert!(matches!(res, Err(LlmError::ToolCalling(_))));
+        assert_eq!(provider.conversations.lock().unwrap().len(), MAX_TOOL_ROUNDS);
+    }
+
+    #[tokio::test]
+    async fn test_tool_errors_are_sent_back() {
+        let tools = adding_tools();
+
+        let unknown = tools.call(&ToolCall { id: "1".to_string(), name: "subtract".to_string(), arguments: json!({}) }).await;
+        let bad_arguments = tools.call(&ToolCall { id: "2".to_string(), name: "add".to_string(), arguments: json!({ "a": "one" }) }).await;
+
+        assert_eq!(unknown, "Error: there is no tool named subtract");
+        assert_eq!(bad_arguments, "Error: a and b must be numbers");
+        assert_eq!(builtin_tools(".").definitions().len(), 3);
+        assert_eq!(builtin_tools(".").select(&[CHECK_URL]).definitions()[0].name, CHECK_URL);
+    }
+
+    #[tokio::test]
+    async fn test_run_build_reports_compiler_errors() {
+        let project_dir = std::env::temp_dir().join("acadia_test_run_build");
+        let _ = std::fs::remove_dir_all(&project_dir);
+        std::fs::create_dir_all(&project_dir).unwrap();
+        let tools = builtin_tools(project_dir.to_str().unwrap());
+        let run_build = ToolCall { id: "1".to_string(), name: RUN_BUILD.to_string(), arguments: json!({}) };
+
+        let without_manifest = tools.call(&run_build).await;
+        std::fs::write(project_dir.join("Cargo.toml"), "[package]\nname = \"broken\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"broken\"\npath = \"main.rs\"\n\n[workspace]\n").unwrap();
+        std::fs::write(project_dir.join("main.rs"), "fn main() { undefined_function() }").unwrap();
+        let broken = tools.call(&run_build).await;
+        std::fs::write(project_dir.join("main.rs"), "fn main() {}").unwrap();
+        let fixed = tools.call(&run_build).await;
+
+        assert!(without_manifest.starts_with("Error:") && without_manifest.contains("has no Cargo.toml"));
+        assert!(broken.starts_with("Error:") && broken.contains("undefined_function"));
+        assert_eq!(fixed, "Build succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_provider_without_tool_support() {
+        let res = make_llm_request_with_tools(Arc::new(LlamaProvider::new()), &adding_tools(), user_message(), "Tester", &AgentState::Working, "count", &CancellationToken::new()).await;
+
+        assert!(matches!(res, Err(LlmError::ToolCalling(_))));
+    }
+}
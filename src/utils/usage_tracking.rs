@@ -0,0 +1,349 @@
+use crossterm::style::Color;
+use serde::Deserialize;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::{models::general::{config::AcadiaConfig, generation::GenerationParams, llm::{LLMCompletion, Message, TokenUsage}, llm_error::LlmError, pricing::PriceTable}, utils::{command_line::LogMessage, token_counting::estimate_message_tokens}};
+
+static LEDGER: OnceLock<Mutex<UsageLedger>> = OnceLock::new();
+
+/// Tokens and cost of one LLM call, attributed to the agent and AI function that made it
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    pub agent_position: String,
+    pub ai_function: String,
+    pub provider: String,
+    pub model: String,
+    /// None when the provider does not report usage
+    pub usage: Option<TokenUsage>,
+    /// None when the model has no entry in the price table
+    pub cost_usd: Option<f64>
+}
+
+/// Totals for one group of records in the cost summary
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsageTotals {
+    pub calls: u32,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+    /// Calls whose usage or price is unknown and therefore missing from `cost_usd`
+    pub unpriced_calls: u32,
+    /// Providers that answered, in the order they first did. More than one means a fallback was used.
+    pub providers: Vec<String>
+}
+
+impl UsageTotals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.calls += 1;
+        if let Some(usage) = record.usage {
+            self.usage.add(usage);
+        }
+        match record.cost_usd {
+            Some(cost) if record.usage.is_some() => self.cost_usd += cost,
+            _ => self.unpriced_calls += 1
+        }
+        if !self.providers.contains(&record.provider) {
+            self.providers.push(record.provider.clone());
+        }
+    }
+}
+
+/// Totals of one AI function of an agent
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionUsage {
+    pub ai_function: String,
+    pub totals: UsageTotals
+}
+
+/// Totals of one agent, broken down per AI function
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentUsage {
+    pub agent_position: String,
+    pub totals: UsageTotals,
+    pub functions: Vec<FunctionUsage>
+}
+
+/// Totals of every call answered by one model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelUsage {
+    pub model: String,
+    pub totals: UsageTotals
+}
+
+/// Hard limits for a whole run, unset limits are not enforced
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RunBudget {
+    pub max_tokens: Option<u64>,
+    pub max_usd: Option<f64>
+}
+
+impl RunBudget {
+
+    /// Fails once the run has used up either limit. Calls without a known price only count towards tokens.
+    pub fn check(&self, spent: &UsageTotals) -> Result<(), LlmError> {
+        if let Some(max_tokens) = self.max_tokens {
+            if spent.usage.total() >= max_tokens {
+                return Err(LlmError::BudgetExceeded(format!("{} of {} tokens used", spent.usage.total(), max_tokens)));
+            }
+        }
+        if let Some(max_usd) = self.max_usd {
+            if spent.cost_usd >= max_usd {
+                return Err(LlmError::BudgetExceeded(format!("${:.4} of ${:.4} spent", spent.cost_usd, max_usd)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every LLM call made during a run, plus the estimated spend of the requests still in flight
+#[derive(Debug, Default)]
+pub struct UsageLedger {
+    records: Vec<UsageRecord>,
+    reserved_usage: TokenUsage,
+    reserved_usd: f64
+}
+
+impl UsageLedger {
+
+    pub fn record(&mut self, record: UsageRecord) {
+        self.records.push(record);
+    }
+
+    /// Checks `budget` against what was spent plus what requests in flight are expected to spend,
+    /// then reserves the estimate of one more request. Parallel requests each see the ones sent before them.
+    pub fn reserve(&mut self, budget: &RunBudget, usage: TokenUsage, cost_usd: f64) -> Result<(), LlmError> {
+        let mut committed = self.totals();
+        committed.usage.add(self.reserved_usage);
+        committed.cost_usd += self.reserved_usd;
+        budget.check(&committed)?;
+
+        self.reserved_usage.add(usage);
+        self.reserved_usd += cost_usd;
+        Ok(())
+    }
+
+    pub fn release(&mut self, usage: TokenUsage, cost_usd: f64) {
+        self.reserved_usage.prompt_tokens = self.reserved_usage.prompt_tokens.saturating_sub(usage.prompt_tokens);
+        self.reserved_usage.completion_tokens = self.reserved_usage.completion_tokens.saturating_sub(usage.completion_tokens);
+        self.reserved_usd = (self.reserved_usd - cost_usd).max(0.0);
+    }
+
+    pub fn totals(&self) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        self.records.iter().for_each(|record| totals.add(record));
+        totals
+    }
+
+    /// Totals per agent, then per AI function of that agent, in the order agents first made a call
+    pub fn summary(&self) -> Vec<AgentUsage> {
+        let mut agents: Vec<AgentUsage> = Vec::new();
+
+        for record in &self.records {
+            let agent_index = match agents.iter().position(|agent| agent.agent_position == record.agent_position) {
+                Some(index) => index,
+                None => {
+                    agents.push(AgentUsage { agent_position: record.agent_position.clone(), totals: UsageTotals::default(), functions: Vec::new() });
+                    agents.len() - 1
+                }
+            };
+            let agent = &mut agents[agent_index];
+            agent.totals.add(record);
+
+            match agent.functions.iter_mut().find(|function| function.ai_function == record.ai_function) {
+                Some(function) => function.totals.add(record),
+                None => {
+                    let mut totals = UsageTotals::default();
+                    totals.add(record);
+                    agent.functions.push(FunctionUsage { ai_function: record.ai_function.clone(), totals });
+                }
+            }
+        }
+        agents
+    }
+
+    /// Totals per model, in the order models first answered
+    pub fn by_model(&self) -> Vec<ModelUsage> {
+        let mut models: Vec<ModelUsage> = Vec::new();
+
+        for record in &self.records {
+            match models.iter_mut().find(|model| model.model == record.model) {
+                Some(model) => model.totals.add(record),
+                None => {
+                    let mut totals = UsageTotals::default();
+                    totals.add(record);
+                    models.push(ModelUsage { model: record.model.clone(), totals });
+                }
+            }
+        }
+        models
+    }
+
+    pub fn print_summary(&self) {
+        LogMessage::Info.print_message("Run cost summary", Color::Rgb { r: 19, g: 214, b: 185 });
+
+        for agent in self.summary() {
+            LogMessage::Info.print_message(&format!("{} | {}", agent.agent_position, format_totals(&agent.totals)), Color::White);
+            for function in agent.functions {
+                LogMessage::Info.print_message(&format!("    {} | {}", function.ai_function, format_totals(&function.totals)), Color::Grey);
+            }
+        }
+        for model in self.by_model() {
+            LogMessage::Info.print_message(&format!("Model {} | {}", model.model, format_totals(&model.totals)), Color::Grey);
+        }
+        LogMessage::Info.print_message(&format!("Run total | {}", format_totals(&self.totals())), Color::Rgb { r: 19, g: 214, b: 185 });
+    }
+}
+
+fn format_totals(totals: &UsageTotals) -> String {
+    let mut line = format!(
+        "{} call(s) | {} prompt + {} completion tokens | ${:.4}",
+        totals.calls, totals.usage.prompt_tokens, totals.usage.completion_tokens, totals.cost_usd
+    );
+    if totals.unpriced_calls > 0 {
+        line.push_str(&format!(" ({} call(s) without usage or price)", totals.unpriced_calls));
+    }
+    if !totals.providers.is_empty() {
+        line.push_str(&format!(" | answered by {}", totals.providers.join(", ")));
+    }
+    line
+}
+
+/// Ledger shared by every agent of the run
+pub fn usage_ledger() -> MutexGuard<'static, UsageLedger> {
+    LEDGER
+        .get_or_init(|| Mutex::new(UsageLedger::default()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn usage_record(completion: &LLMCompletion, agent_position: &str, ai_function: &str, price_table: &PriceTable) -> UsageRecord {
+    UsageRecord {
+        agent_position: agent_position.to_string(),
+        ai_function: ai_function.to_string(),
+        provider: completion.provider.clone(),
+        model: completion.model.clone(),
+        usage: completion.usage,
+        cost_usd: completion.usage.and_then(|usage| price_table.cost(&completion.model, &usage))
+    }
+}
+
+/// Estimated spend of a request in flight, kept in the run ledger until dropped
+#[derive(Debug)]
+pub struct BudgetReservation {
+    usage: TokenUsage,
+    cost_usd: f64
+}
+
+impl Drop for BudgetReservation {
+    fn drop(&mut self) {
+        usage_ledger().release(self.usage, self.cost_usd);
+    }
+}
+
+/// Budget check of one request that also holds its estimated prompt and answer until it is recorded,
+/// so requests sent in parallel can not go over budget together. The answer is estimated at `max_tokens`,
+/// or at the model's output limit when no `max_tokens` is set.
+pub fn reserve_budget(model: &str, messages: &[Message], params: &GenerationParams) -> Result<BudgetReservation, LlmError> {
+    let config = AcadiaConfig::global();
+    let max_output_tokens = params.max_tokens.or_else(|| config.models.capability(model).map(|capability| capability.max_output_tokens));
+    let usage = TokenUsage {
+        prompt_tokens: estimate_message_tokens(model, messages),
+        completion_tokens: max_output_tokens.unwrap_or_default() as u64
+    };
+    let cost_usd = config.pricing.cost(model, &usage).unwrap_or_default();
+
+    usage_ledger().reserve(&config.budget, usage, cost_usd)?;
+    Ok(BudgetReservation { usage, cost_usd })
+}
+
+/// Adds a finished call to the run ledger, priced with the configured price table
+pub fn record_usage(completion: &LLMCompletion, agent_position: &str, ai_function: &str) {
+    let record = usage_record(completion, agent_position, ai_function, &AcadiaConfig::global().pricing);
+    usage_ledger().record(record);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn completion(model: &str, usage: Option<TokenUsage>) -> LLMCompletion {
+        LLMCompletion {
+            content: String::new(),
+            provider: model.to_string(),
+            model: model.to_string(),
+            usage
+        }
+    }
+
+    #[test]
+    fn test_summary_groups_by_agent_and_function() {
+        let prices = PriceTable::default();
+        let usage = Some(TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 0 });
+        let mut ledger = UsageLedger::default();
+
+        ledger.record(usage_record(&completion("gpt-4o", usage), "Solutions Architect", "print_project_scope", &prices));
+        ledger.record(usage_record(&completion("gpt-4o", usage), "Solutions Architect", "print_site_urls", &prices));
+        ledger.record(usage_record(&completion("gpt-4o", usage), "Solutions Architect", "print_project_scope", &prices));
+        ledger.record(usage_record(&completion("Llama3", None), "Project Manager", "convert_user_input_to_goal", &prices));
+
+        let summary = ledger.summary();
+
+        assert_eq!(summary.len(), 2);
+        let architect = &summary[0];
+        assert_eq!(architect.agent_position, "Solutions Architect");
+        assert_eq!(architect.totals.calls, 3);
+        assert_eq!(architect.totals.cost_usd, 7.5);
+        assert_eq!(architect.functions[0].ai_function, "print_project_scope");
+        assert_eq!(architect.functions[0].totals.calls, 2);
+        assert_eq!(summary[1].totals.unpriced_calls, 1);
+        assert_eq!(summary[1].totals.providers, vec!["Llama3"]);
+        assert_eq!(ledger.totals().providers, vec!["gpt-4o", "Llama3"]);
+        assert_eq!(ledger.totals().usage.prompt_tokens, 3_000_000);
+
+        let models = ledger.by_model();
+        assert_eq!(models.iter().map(|model| model.model.as_str()).collect::<Vec<&str>>(), vec!["gpt-4o", "Llama3"]);
+        assert_eq!(models[0].totals.calls, 3);
+        assert_eq!(models[1].totals.unpriced_calls, 1);
+    }
+
+    #[test]
+    fn test_budget_check() {
+        let budget = RunBudget { max_tokens: Some(1_000), max_usd: Some(0.5) };
+        let within = UsageTotals { calls: 1, usage: TokenUsage { prompt_tokens: 400, completion_tokens: 100 }, cost_usd: 0.1, ..UsageTotals::default() };
+        let over_tokens = UsageTotals { calls: 2, usage: TokenUsage { prompt_tokens: 800, completion_tokens: 200 }, cost_usd: 0.2, ..UsageTotals::default() };
+        let over_cost = UsageTotals { calls: 2, usage: TokenUsage { prompt_tokens: 10, completion_tokens: 10 }, cost_usd: 0.75, ..UsageTotals::default() };
+
+        assert!(budget.check(&within).is_ok());
+        assert!(matches!(budget.check(&over_tokens), Err(LlmError::BudgetExceeded(_))));
+        assert!(matches!(budget.check(&over_cost), Err(LlmError::BudgetExceeded(_))));
+        assert!(RunBudget::default().check(&over_cost).is_ok());
+    }
+
+    #[test]
+    fn test_reservations_count_towards_budget() {
+        let budget = RunBudget { max_tokens: None, max_usd: Some(1.0) };
+        let candidate = TokenUsage { prompt_tokens: 1_000, completion_tokens: 1_000 };
+        let mut ledger = UsageLedger::default();
+        ledger.record(usage_record(&completion("gpt-4o", Some(TokenUsage { prompt_tokens: 200_000, completion_tokens: 0 })), "Backend Developer", "print_backend_webserver_code", &PriceTable::default()));
+
+        assert!(ledger.reserve(&budget, candidate, 0.25).is_ok());
+        assert!(ledger.reserve(&budget, candidate, 0.25).is_ok());
+        assert!(matches!(ledger.reserve(&budget, candidate, 0.25), Err(LlmError::BudgetExceeded(_))));
+
+        ledger.release(candidate, 0.25);
+        assert!(ledger.reserve(&budget, candidate, 0.25).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_model_is_unpriced() {
+        let record = usage_record(
+            &completion("mystery-model", Some(TokenUsage { prompt_tokens: 10, completion_tokens: 10 })),
+            "Backend Developer",
+            "print_backend_webserver_code",
+            &PriceTable::default()
+        );
+
+        assert!(record.cost_usd.is_none());
+    }
+}